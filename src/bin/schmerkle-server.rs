@@ -0,0 +1,70 @@
+extern crate schmerkle;
+
+use std::env;
+use std::fs::File;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
+
+use schmerkle::hmac::HmacSha256;
+use schmerkle::log::TransparencyLog;
+use schmerkle::server::LogServer;
+use schmerkle::rfc6962::Rfc6962;
+
+
+// Serves an RFC 6962 (SHA-256) transparency log on localhost, see server.rs
+//  for the endpoints. Heads are signed with HMAC-SHA256 under --key,
+//  which is required: monitors need the same key to check them.
+// With --state the log is loaded from the file on start
+//  and saved to it after every head.
+
+const USAGE: &'static str = "usage: schmerkle-server --key KEY [--port PORT] [--interval SECONDS] [--state FILE]";
+
+fn main() {
+    if let Err(message) = run(env::args().skip(1).collect()) {
+        eprintln!("{}", message);
+        process::exit(2)
+    }
+}
+
+fn run(args: Vec<String>) -> Result<(), String> {
+    let mut port = 6962u16;
+    let mut key = None;
+    let mut interval = 1u64;
+    let mut state = None;
+
+    let mut args = args.into_iter();
+    while let Some(flag) = args.next() {
+        let value = args.next().ok_or(USAGE.to_string())?;
+        match flag.as_str() {
+            "--port" => port = value.parse().map_err(|_| format!("invalid port: {}", value))?,
+            "--key" => key = Some(value),
+            "--interval" => interval = value.parse().map_err(|_| format!("invalid interval: {}", value))?,
+            "--state" => state = Some(PathBuf::from(value)),
+            _ => return Err(USAGE.to_string())
+        }
+    }
+
+    let key = match key {
+        Some(ref key) if !key.is_empty() => key,
+        _ => return Err(USAGE.to_string())
+    };
+    let signer = HmacSha256::new(key.as_bytes());
+    let log = match state {
+        Some(ref path) if path.exists() => {
+            let mut file = File::open(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+            TransparencyLog::load(&mut file, Rfc6962, signer)
+                .map_err(|err| format!("{}: {}", path.display(), err))?
+        },
+        _ => TransparencyLog::new(Rfc6962, signer)
+    };
+    let mut server = LogServer::new(log, Duration::from_secs(interval));
+    if let Some(path) = state {
+        server = server.with_state(path);
+    }
+
+    let listener = TcpListener::bind(("127.0.0.1", port)).map_err(|err| err.to_string())?;
+    eprintln!("listening on http://127.0.0.1:{}/ct/v1/", port);
+    server.serve(listener).map_err(|err| err.to_string())
+}
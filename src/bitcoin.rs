@@ -0,0 +1,377 @@
+use std::cmp::min;
+use std::hash::Hasher;
+
+use hash::{MerkleHasher, BuildMerkleHasher};
+use tree::{HashTree, hash_pair};
+use sha256::Sha256;
+use codec::{DecodeError, Reader};
+use hex::{Hex, HexError, to_hex, ct_eq};
+
+
+// Bitcoin transaction Merkle trees and SPV proofs.
+//
+// A block's Merkle root hashes its txids pairwise with double SHA-256,
+//  pairing the last hash of a level with itself when the level has an
+//  odd length. That's how MerkleTree pairs a lonely child too, so a
+//  HashTree<BuildDoubleSha256> of the txids has the block's root.
+//  BuildDoubleSha256 hashes branches over the two raw hashes, without
+//  the length prefixes Hash puts in front of slices, as Bitcoin just
+//  concatenates them.
+//
+// Hashes are kept in internal byte order, the one they're hashed and
+//  sent in. Explorers and RPCs show them reversed, parse_hash() and
+//  display_hash() convert from and to that.
+//
+// PartialMerkleTree is Bitcoin's CPartialMerkleTree, the proof in a
+//  merkleblock message. Nodes are visited depth first from the root,
+//  each gets a flag saying whether a matched txid is below it; nodes
+//  without one, and matched leaves, also give their hash. Serialized:
+//
+//   transaction count (u32, little-endian)
+//   hash count (compact size), then the hashes
+//   flag byte count (compact size), then the flags, 8 to a byte,
+//    lowest bit first
+//
+// MerkleBlock is the 80 byte block header followed by that.
+//
+// Since the last hash of a level is paired with itself, a block whose
+//  last txids repeat has the same root as one without them
+//  (CVE-2012-2459). extract_matches() refuses any node whose children
+//  are equal, as Bitcoin Core does.
+
+pub type Hash256 = [u8; 32];
+
+pub type TxTree = HashTree<BuildDoubleSha256>;
+
+// Largest transaction count a block can have (Bitcoin Core's
+//  MAX_BLOCK_WEIGHT / MIN_TRANSACTION_WEIGHT)
+pub const MAX_TRANSACTIONS: u32 = 4_000_000 / 240;
+
+#[derive(Clone)]
+pub struct DoubleSha256 {
+    inner: Sha256
+}
+
+#[derive(Clone, Default)]
+pub struct BuildDoubleSha256;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PartialMerkleTree {
+    pub tx_count: u32,
+    pub hashes: Vec<Hash256>,
+    pub flags: Vec<bool>
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MerkleBlock {
+    pub header: [u8; 80],
+    pub tree: PartialMerkleTree
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PartialTreeError {
+    NoTransactions,
+    TooManyTransactions(u32),
+    TooManyHashes,
+    NotEnoughFlags,
+    NotEnoughHashes,
+    UnusedFlags,
+    UnusedHashes,
+    // Both children of a node have the same hash
+    DuplicateSibling,
+    RootMismatch
+}
+
+pub fn double_sha256(data: &[u8]) -> Hash256 {
+    Sha256::digest(&Sha256::digest(data))
+}
+
+pub fn tx_tree(txids: &[Hash256]) -> TxTree {
+    let mut tree = HashTree::with_hasher(BuildDoubleSha256);
+    tree.insert_hashes(txids.iter().cloned());
+    tree
+}
+
+// None for no transactions, blocks always have the coinbase one
+pub fn merkle_root(txids: &[Hash256]) -> Option<Hash256> {
+    tx_tree(txids).root_hash()
+}
+
+// Hash in the reversed hex txids and block hashes are shown in
+pub fn parse_hash(hex: &str) -> Result<Hash256, HexError> {
+    let mut hash = hex.parse::<Hex<Hash256>>()?.into_inner();
+    hash.reverse();
+    Ok(hash)
+}
+
+pub fn display_hash(hash: &Hash256) -> String {
+    let mut reversed = *hash;
+    reversed.reverse();
+    to_hex(&reversed)
+}
+
+impl Hasher for DoubleSha256 {
+    fn finish(&self) -> u64 {
+        let digest = self.finish_full();
+        let mut head = [0u8; 8];
+        head.copy_from_slice(&digest[..8]);
+        u64::from_be_bytes(head)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.inner.update(bytes)
+    }
+}
+
+impl MerkleHasher for DoubleSha256 {
+    type Output = Hash256;
+
+    fn finish_full(&self) -> Hash256 {
+        Sha256::digest(&self.inner.clone().finalize())
+    }
+}
+
+impl BuildMerkleHasher for BuildDoubleSha256 {
+    type Hasher = DoubleSha256;
+
+    fn build_hasher(&self) -> DoubleSha256 {
+        DoubleSha256 { inner: Sha256::new() }
+    }
+
+    fn hash_branch(&self, left: &[u8], right: &[u8]) -> Hash256 {
+        let mut hasher = self.build_hasher();
+        hasher.write(left);
+        hasher.write(right);
+        hasher.finish_full()
+    }
+}
+
+impl PartialMerkleTree {
+    // Proof for the txids at the positions where `matches` is true,
+    //  None unless there's one flag per txid and no more than a block
+    //  can hold
+    pub fn from_tree(tree: &TxTree, matches: &[bool]) -> Option<PartialMerkleTree> {
+        if tree.leaf_count() != Some(matches.len()) || matches.len() > MAX_TRANSACTIONS as usize {
+            return None
+        }
+        let mut partial = PartialMerkleTree {
+            tx_count: matches.len() as u32,
+            hashes: vec![],
+            flags: vec![]
+        };
+        partial.build(tree, matches, tree.height(), 0);
+        Some(partial)
+    }
+
+    fn build(&mut self, tree: &TxTree, matches: &[bool], level: usize, index: usize) {
+        let start = index << level;
+        let end = min((index + 1) << level, matches.len());
+        let matched = matches[start..end].iter().any(|&matched| matched);
+        self.flags.push(matched);
+        if level == 0 || !matched {
+            let node = tree.node_at(level, index).expect("every visited node is in the tree");
+            self.hashes.push(node.hash_value());
+        } else {
+            self.build(tree, matches, level - 1, 2 * index);
+            if (2 * index + 1) << (level - 1) < matches.len() {
+                self.build(tree, matches, level - 1, 2 * index + 1);
+            }
+        }
+    }
+
+    // Root the tree leads to, and the matched txids with their positions
+    //  in the block
+    pub fn extract_matches(&self) -> Result<(Hash256, Vec<(usize, Hash256)>), PartialTreeError> {
+        if self.tx_count == 0 {
+            return Err(PartialTreeError::NoTransactions)
+        }
+        if self.tx_count > MAX_TRANSACTIONS {
+            return Err(PartialTreeError::TooManyTransactions(self.tx_count))
+        }
+        if self.hashes.len() > self.tx_count as usize {
+            return Err(PartialTreeError::TooManyHashes)
+        }
+        if self.flags.len() < self.hashes.len() {
+            return Err(PartialTreeError::NotEnoughFlags)
+        }
+        let mut height = 0;
+        while self.width(height) > 1 {
+            height += 1;
+        }
+        let mut extraction = Extraction { flags_used: 0, hashes_used: 0, matches: vec![] };
+        let root = self.extract(&mut extraction, height, 0)?;
+        // Flags come in whole bytes, only the padding may be left over
+        if (extraction.flags_used + 7) / 8 != (self.flags.len() + 7) / 8 {
+            return Err(PartialTreeError::UnusedFlags)
+        }
+        if extraction.hashes_used != self.hashes.len() {
+            return Err(PartialTreeError::UnusedHashes)
+        }
+        Ok((root, extraction.matches))
+    }
+
+    fn extract(&self, extraction: &mut Extraction, level: usize, index: usize) -> Result<Hash256, PartialTreeError> {
+        let matched = *self.flags.get(extraction.flags_used).ok_or(PartialTreeError::NotEnoughFlags)?;
+        extraction.flags_used += 1;
+        if level == 0 || !matched {
+            let hash = *self.hashes.get(extraction.hashes_used).ok_or(PartialTreeError::NotEnoughHashes)?;
+            extraction.hashes_used += 1;
+            if level == 0 && matched {
+                extraction.matches.push((index, hash));
+            }
+            return Ok(hash)
+        }
+        let left = self.extract(extraction, level - 1, 2 * index)?;
+        let right = if 2 * index + 1 < self.width(level - 1) {
+            let right = self.extract(extraction, level - 1, 2 * index + 1)?;
+            if ct_eq(&left, &right) {
+                return Err(PartialTreeError::DuplicateSibling)
+            }
+            right
+        } else {
+            left
+        };
+        Ok(hash_pair(&left, &right, &BuildDoubleSha256))
+    }
+
+    // Number of nodes at the given level
+    fn width(&self, level: usize) -> usize {
+        (self.tx_count as usize + (1 << level) - 1) >> level
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![];
+        self.encode_to(&mut out);
+        out
+    }
+
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.tx_count.to_le_bytes());
+        put_compact_size(out, self.hashes.len() as u64);
+        for hash in &self.hashes {
+            out.extend_from_slice(hash);
+        }
+        let mut flag_bytes = vec![0u8; (self.flags.len() + 7) / 8];
+        for (i, &flag) in self.flags.iter().enumerate() {
+            if flag {
+                flag_bytes[i / 8] |= 1 << (i % 8);
+            }
+        }
+        put_compact_size(out, flag_bytes.len() as u64);
+        out.extend_from_slice(&flag_bytes);
+    }
+
+    // Flags are read back in whole bytes, padding included
+    pub fn decode(bytes: &[u8]) -> Result<PartialMerkleTree, DecodeError> {
+        let mut reader = Reader { bytes: bytes };
+        let tree = PartialMerkleTree::decode_from(&mut reader)?;
+        if !reader.bytes.is_empty() {
+            return Err(DecodeError::TrailingBytes)
+        }
+        Ok(tree)
+    }
+
+    fn decode_from(reader: &mut Reader) -> Result<PartialMerkleTree, DecodeError> {
+        let mut tx_count = [0u8; 4];
+        tx_count.copy_from_slice(reader.take(4)?);
+        let mut hashes = vec![];
+        for _ in 0..compact_size(reader)? {
+            let mut hash = [0u8; 32];
+            hash.copy_from_slice(reader.take(32)?);
+            hashes.push(hash);
+        }
+        let flag_bytes = compact_size(reader)?;
+        if flag_bytes > reader.bytes.len() as u64 {
+            return Err(DecodeError::UnexpectedEnd)
+        }
+        let flags = reader.take(flag_bytes as usize)?.iter()
+            .flat_map(|&byte| (0..8).map(move |bit| byte >> bit & 1 == 1))
+            .collect();
+        Ok(PartialMerkleTree {
+            tx_count: u32::from_le_bytes(tx_count),
+            hashes: hashes,
+            flags: flags
+        })
+    }
+}
+
+struct Extraction {
+    flags_used: usize,
+    hashes_used: usize,
+    matches: Vec<(usize, Hash256)>
+}
+
+impl MerkleBlock {
+    pub fn block_hash(&self) -> Hash256 {
+        double_sha256(&self.header)
+    }
+
+    // Merkle root the header commits to
+    pub fn merkle_root(&self) -> Hash256 {
+        let mut root = [0u8; 32];
+        root.copy_from_slice(&self.header[36..68]);
+        root
+    }
+
+    // Matched txids with their positions, if the partial tree leads
+    //  to the header's root
+    pub fn verify(&self) -> Result<Vec<(usize, Hash256)>, PartialTreeError> {
+        let (root, matches) = self.tree.extract_matches()?;
+        if !ct_eq(&root, &self.merkle_root()) {
+            return Err(PartialTreeError::RootMismatch)
+        }
+        Ok(matches)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = self.header.to_vec();
+        self.tree.encode_to(&mut out);
+        out
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<MerkleBlock, DecodeError> {
+        let mut reader = Reader { bytes: bytes };
+        let mut header = [0u8; 80];
+        header.copy_from_slice(reader.take(80)?);
+        let tree = PartialMerkleTree::decode_from(&mut reader)?;
+        if !reader.bytes.is_empty() {
+            return Err(DecodeError::TrailingBytes)
+        }
+        Ok(MerkleBlock {
+            header: header,
+            tree: tree
+        })
+    }
+}
+
+fn put_compact_size(out: &mut Vec<u8>, value: u64) {
+    if value < 0xfd {
+        out.push(value as u8);
+    } else if value <= 0xffff {
+        out.push(0xfd);
+        out.extend_from_slice(&(value as u16).to_le_bytes());
+    } else if value <= 0xffff_ffff {
+        out.push(0xfe);
+        out.extend_from_slice(&(value as u32).to_le_bytes());
+    } else {
+        out.push(0xff);
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+// Only the shortest encoding of a number is accepted
+fn compact_size(reader: &mut Reader) -> Result<u64, DecodeError> {
+    let (len, min) = match reader.byte()? {
+        0xfd => (2, 0xfd),
+        0xfe => (4, 0x1_0000),
+        0xff => (8, 0x1_0000_0000),
+        small => return Ok(small as u64)
+    };
+    let mut value = [0u8; 8];
+    value[..len].copy_from_slice(reader.take(len)?);
+    let value = u64::from_le_bytes(value);
+    if value < min {
+        return Err(DecodeError::NonCanonical)
+    }
+    Ok(value)
+}
@@ -0,0 +1,198 @@
+use std::convert::TryFrom;
+use std::io::{self, Read};
+use std::hash::Hasher;
+
+use hash::{BuildMerkleHasher, MerkleHasher};
+use node::HashValue;
+use tree::{HashTree, Proof, verify_at};
+
+
+// Chunked hashing of large streams, for verifying data as it arrives.
+//
+// A stream is cut into chunks of chunk_size bytes (the last one may be
+//  shorter) and each chunk becomes a leaf of a HashTree: the hash of
+//  its length as a big-endian u64 followed by its bytes. Only one chunk
+//  is held in memory at a time.
+// Leaves don't depend on the platform, and roots don't either with
+//  a hasher whose hash_branch() hashes the raw pair, like BuildSha256.
+//
+// The sender hashes the stream once with ChunkedTree::from_reader(),
+//  publishes root, length and chunk size, and sends every chunk with
+//  its proof(). The receiver checks each chunk on its own with a
+//  ChunkVerifier, which also makes sure the chunk has the right size
+//  and that the proof leads to the claimed position, so chunks can't
+//  be reordered.
+
+pub struct ChunkedTree<S>
+where
+    S: BuildMerkleHasher
+{
+    tree: HashTree<S>,
+    chunk_size: usize,
+    len: u64
+}
+
+pub struct ChunkVerifier<S>
+where
+    S: BuildMerkleHasher
+{
+    root: HashValue<S>,
+    chunk_size: usize,
+    chunk_count: usize,
+    len: u64,
+    hasher_builder: S
+}
+
+// Reads a stream chunk by chunk, every chunk but the last is full
+pub struct Chunks<R> {
+    reader: R,
+    chunk_size: usize,
+    done: bool
+}
+
+pub fn chunks<R: Read>(reader: R, chunk_size: usize) -> Chunks<R> {
+    assert!(chunk_size > 0, "chunk size must not be 0");
+    Chunks {
+        reader: reader,
+        chunk_size: chunk_size,
+        done: false
+    }
+}
+
+impl<R: Read> Iterator for Chunks<R> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<io::Result<Vec<u8>>> {
+        if self.done {
+            return None
+        }
+        let mut chunk = vec![0u8; self.chunk_size];
+        let mut filled = 0;
+        while filled < self.chunk_size {
+            match self.reader.read(&mut chunk[filled..]) {
+                Ok(0) => break,
+                Ok(count) => filled += count,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err))
+                }
+            }
+        }
+        if filled < self.chunk_size {
+            self.done = true;
+            if filled == 0 {
+                return None
+            }
+            chunk.truncate(filled);
+        }
+        Some(Ok(chunk))
+    }
+}
+
+impl<S> ChunkedTree<S>
+where
+    S: BuildMerkleHasher
+{
+    pub fn from_reader<R: Read>(reader: R, chunk_size: usize, hasher_builder: S) -> io::Result<ChunkedTree<S>> {
+        let mut hashes = vec![];
+        let mut len = 0;
+        for chunk in chunks(reader, chunk_size) {
+            let chunk = chunk?;
+            len += chunk.len() as u64;
+            hashes.push(chunk_hash(&chunk, &hasher_builder));
+        }
+        let mut tree = HashTree::with_hasher(hasher_builder);
+        tree.insert_hashes(hashes);
+        Ok(ChunkedTree {
+            tree: tree,
+            chunk_size: chunk_size,
+            len: len
+        })
+    }
+
+    pub fn root_hash(&self) -> Option<HashValue<S>> {
+        self.tree.root_hash()
+    }
+
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    // Length of the whole stream in bytes
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.tree.leaf_count().unwrap_or(0)
+    }
+
+    pub fn proof(&self, index: usize) -> Option<Vec<Proof<HashValue<S>>>> {
+        self.tree.index_proof(index)
+    }
+
+    pub fn tree(&self) -> &HashTree<S> {
+        &self.tree
+    }
+
+    // Verifier for the receiving side, None for an empty stream
+    pub fn verifier(&self) -> Option<ChunkVerifier<S>> {
+        let root = self.root_hash()?;
+        ChunkVerifier::new(root, self.len, self.chunk_size, self.tree.hasher_builder())
+    }
+}
+
+impl<S> ChunkVerifier<S>
+where
+    S: BuildMerkleHasher
+{
+    // None if the stream has more chunks than fit in a usize
+    pub fn new(root: HashValue<S>, len: u64, chunk_size: usize, hasher_builder: S) -> Option<ChunkVerifier<S>> {
+        assert!(chunk_size > 0, "chunk size must not be 0");
+        let size = chunk_size as u64;
+        let chunk_count = usize::try_from(len / size + (len % size != 0) as u64).ok()?;
+        Some(ChunkVerifier {
+            root: root,
+            chunk_size: chunk_size,
+            chunk_count: chunk_count,
+            len: len,
+            hasher_builder: hasher_builder
+        })
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.chunk_count
+    }
+
+    // Size the chunk at the given position must have
+    pub fn chunk_len(&self, index: usize) -> Option<usize> {
+        if index >= self.chunk_count() {
+            return None
+        }
+        let start = index as u64 * self.chunk_size as u64;
+        Some(if self.len - start < self.chunk_size as u64 {
+            (self.len - start) as usize
+        } else {
+            self.chunk_size
+        })
+    }
+
+    pub fn verify(&self, index: usize, chunk: &[u8], proof: &[Proof<HashValue<S>>]) -> bool {
+        if self.chunk_len(index) != Some(chunk.len()) {
+            return false
+        }
+        verify_at(&chunk_hash(chunk, &self.hasher_builder), proof, index, self.chunk_count(), &self.root, &self.hasher_builder)
+            .is_ok()
+    }
+}
+
+fn chunk_hash<S>(chunk: &[u8], hasher_builder: &S) -> HashValue<S>
+where
+    S: BuildMerkleHasher
+{
+    let mut hasher = hasher_builder.build_hasher();
+    hasher.write(&(chunk.len() as u64).to_be_bytes());
+    hasher.write(chunk);
+    hasher.finish_full()
+}
@@ -0,0 +1,61 @@
+// Byte encoding shared by the wire and file formats: sync messages,
+//  signed tree heads, saved logs, and the Bitcoin and RLP decoders.
+//
+// Numbers are u64 big-endian, byte strings are their length as such
+//  a number followed by the bytes. Reader takes them apart again,
+//  never reading past the end or allocating more than is there.
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DecodeError {
+    UnexpectedEnd,
+    UnknownTag(u8),
+    // Format version this build can't read
+    UnknownVersion(u8),
+    TrailingBytes,
+    // Hash of the wrong length for the hasher
+    BadHash,
+    // Not the shortest encoding of the value
+    NonCanonical
+}
+
+pub(crate) fn put_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+pub(crate) fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    put_u64(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+pub(crate) struct Reader<'a> {
+    pub(crate) bytes: &'a [u8]
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        if self.bytes.len() < len {
+            return Err(DecodeError::UnexpectedEnd)
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    pub(crate) fn byte(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, DecodeError> {
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Ok(u64::from_be_bytes(buf))
+    }
+
+    pub(crate) fn bytes(&mut self) -> Result<Vec<u8>, DecodeError> {
+        let len = self.u64()?;
+        if len > self.bytes.len() as u64 {
+            return Err(DecodeError::UnexpectedEnd)
+        }
+        Ok(self.take(len as usize)?.to_vec())
+    }
+}
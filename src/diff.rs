@@ -0,0 +1,103 @@
+use std::cmp::{min, max};
+use std::hash::Hash;
+use std::ops::Range;
+
+use hash::BuildMerkleHasher;
+use node::Child;
+use tree::MerkleTree;
+
+
+// diff() finds which leaves differ between two trees.
+//
+// Both trees are walked top-down in lockstep, comparing hashes
+//  of nodes at the same position and descending only into
+//  mismatched subtrees, so equal regions are never visited.
+//
+// Positions line up because the shape of a tree depends only on
+//  its leaf count: a node of height h and index i always covers
+//  leaves [i * 2^h, (i + 1) * 2^h). A shorter tree is compared
+//  against the leftmost subtree of the same height in the taller one.
+//
+// Nodes are compared by hash AND leaf count, since a siblingless
+//  node is hashed twice (!!!) and would otherwise look equal to
+//  a node with two identical children.
+//
+// The result is a sorted list of non-overlapping leaf index ranges.
+// Leaves present in only one of the trees are reported as different.
+
+pub fn diff<V, S>(first: &MerkleTree<V, S>, second: &MerkleTree<V, S>) -> Vec<Range<usize>>
+where
+    V: Hash + Clone,
+    S: BuildMerkleHasher
+{
+    let mut ranges = vec![];
+    match (first.root(), second.root()) {
+        (&Some(ref first_root), &Some(ref second_root)) => {
+            let height = min(first_root.height(), second_root.height());
+            let first_node = left_spine(first_root, height);
+            let second_node = left_spine(second_root, height);
+            diff_nodes(Some(first_node), Some(second_node), height, 0, &mut ranges);
+
+            // Leaves past the aligned subtrees exist only in the taller tree
+            let aligned = 1 << height;
+            let longest = max(first_root.leaf_count(), second_root.leaf_count());
+            if longest > aligned {
+                push_range(&mut ranges, aligned..longest);
+            }
+        },
+        (&Some(ref root), _) | (_, &Some(ref root)) => push_range(&mut ranges, 0..root.leaf_count()),
+        _ => ()
+    }
+    ranges
+}
+
+fn diff_nodes<V, S>(
+    first: Option<&Child<V, S>>,
+    second: Option<&Child<V, S>>,
+    height: usize,
+    offset: usize,
+    ranges: &mut Vec<Range<usize>>)
+where
+    V: Hash + Clone,
+    S: BuildMerkleHasher
+{
+    match (first, second) {
+        (Some(first), Some(second)) => {
+            if first.leaf_count() == second.leaf_count() && first.hash_ref() == second.hash_ref() {
+                return
+            }
+            if height == 0 {
+                push_range(ranges, offset..offset + 1);
+            } else {
+                let half = 1 << (height - 1);
+                diff_nodes(first.left().as_ref(), second.left().as_ref(), height - 1, offset, ranges);
+                diff_nodes(first.right().as_ref(), second.right().as_ref(), height - 1, offset + half, ranges);
+            }
+        },
+        (Some(node), None) | (None, Some(node)) => push_range(ranges, offset..offset + node.leaf_count()),
+        _ => ()
+    }
+}
+
+// Follows left children down to the given height
+fn left_spine<V, S>(node: &Child<V, S>, height: usize) -> &Child<V, S>
+where
+    V: Hash + Clone,
+    S: BuildMerkleHasher
+{
+    match node.left() {
+        &Some(ref left) if node.height() > height => left_spine(left, height),
+        _ => node
+    }
+}
+
+// Appends a range, merging it with the previous one when they touch
+pub(crate) fn push_range(ranges: &mut Vec<Range<usize>>, range: Range<usize>) {
+    if let Some(last) = ranges.last_mut() {
+        if last.end == range.start {
+            last.end = range.end;
+            return
+        }
+    }
+    ranges.push(range)
+}
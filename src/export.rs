@@ -0,0 +1,110 @@
+use std::hash::Hash;
+use std::fmt::Write;
+
+use hash::BuildMerkleHasher;
+use tree::MerkleTree;
+use node::{Node, Child};
+use hex::to_hex;
+
+
+// Structure dumps for debugging and figures.
+//
+// to_dot() writes a Graphviz digraph, render it with e.g.
+//  `dot -Tsvg tree.dot > tree.svg`.
+// Final nodes are filled, leaves are labeled with their index.
+//  A lonely left child is hashed twice in its parent, so the missing
+//  right child is drawn as a dashed copy of it.
+//
+// to_json() writes nested objects with hash, height, leaf_count,
+//  is_final and the left and right children (null when missing).
+//  An empty tree is null.
+
+// Hex characters of a hash shown in DOT labels
+const LABEL_LENGTH: usize = 8;
+
+pub fn to_dot<V, S>(tree: &MerkleTree<V, S>) -> String
+where
+    V: Hash + Clone,
+    S: BuildMerkleHasher
+{
+    let mut out = String::from("digraph merkle {\n    node [shape=box, fontname=monospace];\n");
+    if let &Some(ref root) = tree.root() {
+        let mut next_id = 0;
+        dot_node(root, 0, &mut next_id, &mut out);
+    }
+    out.push_str("}\n");
+    out
+}
+
+pub fn to_json<V, S>(tree: &MerkleTree<V, S>) -> String
+where
+    V: Hash + Clone,
+    S: BuildMerkleHasher
+{
+    let mut out = String::new();
+    json_node(tree.root(), &mut out);
+    out
+}
+
+// Writes the node and its subtree, returns the node's id
+fn dot_node<V, S>(node: &Child<V, S>, first_leaf: usize, next_id: &mut usize, out: &mut String) -> usize
+where
+    V: Hash + Clone,
+    S: BuildMerkleHasher
+{
+    let id = *next_id;
+    *next_id += 1;
+
+    let label = match **node {
+        Node::Leaf(_) => format!("#{}\\n{}", first_leaf, short_hex(node.hash_ref())),
+        Node::Branch(_) => short_hex(node.hash_ref())
+    };
+    let style = if node.is_final() {
+        ", style=filled, fillcolor=lightblue"
+    } else {
+        ""
+    };
+    writeln!(out, "    n{} [label=\"{}\"{}];", id, label, style).unwrap();
+
+    if let &Some(ref left) = node.left() {
+        let left_id = dot_node(left, first_leaf, next_id, out);
+        writeln!(out, "    n{} -> n{};", id, left_id).unwrap();
+        match node.right() {
+            &Some(ref right) => {
+                let right_id = dot_node(right, first_leaf + (1 << (node.height() - 1)), next_id, out);
+                writeln!(out, "    n{} -> n{};", id, right_id).unwrap();
+            },
+            &None => {
+                let dup_id = *next_id;
+                *next_id += 1;
+                writeln!(out, "    n{} [label=\"dup\\n{}\", style=dashed];", dup_id, short_hex(left.hash_ref())).unwrap();
+                writeln!(out, "    n{} -> n{} [style=dashed];", id, dup_id).unwrap();
+            }
+        }
+    }
+    id
+}
+
+fn json_node<V, S>(node: &Option<Child<V, S>>, out: &mut String)
+where
+    V: Hash + Clone,
+    S: BuildMerkleHasher
+{
+    match node {
+        &Some(ref node) => {
+            write!(out, "{{\"hash\":\"{}\",\"height\":{},\"leaf_count\":{},\"is_final\":{},\"left\":",
+                to_hex(node.hash_ref()), node.height(), node.leaf_count(), node.is_final()).unwrap();
+            json_node(node.left(), out);
+            out.push_str(",\"right\":");
+            json_node(node.right(), out);
+            out.push('}');
+        },
+        &None => out.push_str("null")
+    }
+}
+
+fn short_hex(bytes: &[u8]) -> String {
+    let mut hex = to_hex(bytes);
+    hex.truncate(LABEL_LENGTH);
+    hex
+}
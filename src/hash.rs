@@ -1,4 +1,5 @@
-use std::hash::Hasher;
+use std::hash::{Hash, Hasher};
+use std::fmt::Debug;
 
 // Standard Hasher trait is constrained to 64bit hashes
 // by finish() method.
@@ -6,8 +7,10 @@ use std::hash::Hasher;
 // Also MerkleHasher works with std::hash::Hash (yay!).
 pub trait MerkleHasher: Hasher
 {
+    type Output: Digest;
+
     // Just like finish, but not constrained to 64bits
-    fn finish_full(&self) -> Box<[u8]>;
+    fn finish_full(&self) -> Self::Output;
 }
 
 
@@ -15,4 +18,40 @@ pub trait MerkleHasher: Hasher
 pub trait BuildMerkleHasher : Clone {
     type Hasher: MerkleHasher;
     fn build_hasher(&self) -> Self::Hasher;
-}
\ No newline at end of file
+
+    // Hash of a branch from the hashes of its children, a lonely child
+    //  comes as both. Override to combine them some other way.
+    fn hash_branch(&self, left: &[u8], right: &[u8]) -> <Self::Hasher as MerkleHasher>::Output {
+        let mut hasher = self.build_hasher();
+        left.hash(&mut hasher);
+        right.hash(&mut hasher);
+        hasher.finish_full()
+    }
+}
+
+
+// Output of a MerkleHasher.
+// Fixed-size arrays keep digest length in the type, so hashes of
+//  different lengths can't be mixed up. Box<[u8]> is there for
+//  hashers whose output length is only known at runtime.
+pub trait Digest: AsRef<[u8]> + Clone + Eq + Hash + Debug + Send + Sync {
+    // None if the length doesn't fit
+    fn from_bytes(bytes: &[u8]) -> Option<Self>;
+}
+
+impl<const N: usize> Digest for [u8; N] {
+    fn from_bytes(bytes: &[u8]) -> Option<[u8; N]> {
+        if bytes.len() != N {
+            return None
+        }
+        let mut digest = [0u8; N];
+        digest.copy_from_slice(bytes);
+        Some(digest)
+    }
+}
+
+impl Digest for Box<[u8]> {
+    fn from_bytes(bytes: &[u8]) -> Option<Box<[u8]>> {
+        Some(Box::from(bytes))
+    }
+}
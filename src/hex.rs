@@ -0,0 +1,131 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+use hash::Digest;
+
+
+// Hex encoding of hashes.
+//
+// Output is lowercase with two digits per byte, so it always
+//  parses back; parsing also takes uppercase.
+//
+// Hex wraps a digest for printing ({} and {:x}) and parsing
+//  (str::parse). Its equality is constant-time, as roots and
+//  proofs coming from the outside are compared against it.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HexError {
+    OddLength,
+    InvalidDigit(usize),
+    WrongLength(usize)
+}
+
+#[derive(Clone)]
+pub struct Hex<D>(pub D);
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for &byte in bytes {
+        hex.push(DIGITS[(byte >> 4) as usize] as char);
+        hex.push(DIGITS[(byte & 0xf) as usize] as char);
+    }
+    hex
+}
+
+pub fn from_hex(hex: &str) -> Result<Vec<u8>, HexError> {
+    let digits = hex.as_bytes();
+    if digits.len() % 2 != 0 {
+        return Err(HexError::OddLength)
+    }
+    let mut bytes = Vec::with_capacity(digits.len() / 2);
+    for (i, pair) in digits.chunks(2).enumerate() {
+        let high = digit(pair[0]).ok_or(HexError::InvalidDigit(i * 2))?;
+        let low = digit(pair[1]).ok_or(HexError::InvalidDigit(i * 2 + 1))?;
+        bytes.push(high << 4 | low);
+    }
+    Ok(bytes)
+}
+
+// Equality that takes the same time wherever the first difference is,
+//  lengths are not secret
+pub fn ct_eq(first: &[u8], second: &[u8]) -> bool {
+    if first.len() != second.len() {
+        return false
+    }
+    let difference = first.iter()
+        .zip(second.iter())
+        .fold(0u8, |acc, (a, b)| acc | (a ^ b));
+    difference == 0
+}
+
+const DIGITS: &'static [u8; 16] = b"0123456789abcdef";
+
+fn digit(character: u8) -> Option<u8> {
+    match character {
+        b'0'..=b'9' => Some(character - b'0'),
+        b'a'..=b'f' => Some(character - b'a' + 10),
+        b'A'..=b'F' => Some(character - b'A' + 10),
+        _ => None
+    }
+}
+
+impl<D: AsRef<[u8]>> Hex<D> {
+    pub fn into_inner(self) -> D {
+        self.0
+    }
+}
+
+impl<D: AsRef<[u8]>> fmt::Display for Hex<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::LowerHex::fmt(self, f)
+    }
+}
+
+impl<D: AsRef<[u8]>> fmt::LowerHex for Hex<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(&to_hex(self.0.as_ref()))
+    }
+}
+
+impl<D: AsRef<[u8]>> fmt::Debug for Hex<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Hex({})", to_hex(self.0.as_ref()))
+    }
+}
+
+impl<D: AsRef<[u8]>> PartialEq for Hex<D> {
+    fn eq(&self, other: &Hex<D>) -> bool {
+        ct_eq(self.0.as_ref(), other.0.as_ref())
+    }
+}
+
+impl<D: AsRef<[u8]>> Eq for Hex<D> {}
+
+// Over the same bytes PartialEq compares, so equal values hash alike
+impl<D: AsRef<[u8]>> Hash for Hex<D> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.as_ref().hash(state)
+    }
+}
+
+impl<D: Digest> FromStr for Hex<D> {
+    type Err = HexError;
+
+    fn from_str(hex: &str) -> Result<Hex<D>, HexError> {
+        let bytes = from_hex(hex)?;
+        D::from_bytes(&bytes)
+            .map(Hex)
+            .ok_or(HexError::WrongLength(bytes.len()))
+    }
+}
+
+impl fmt::Display for HexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &HexError::OddLength => write!(f, "odd number of hex digits"),
+            &HexError::InvalidDigit(position) => write!(f, "invalid hex digit at {}", position),
+            &HexError::WrongLength(length) => write!(f, "{} bytes is not a valid hash length", length)
+        }
+    }
+}
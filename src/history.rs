@@ -0,0 +1,174 @@
+use std::hash::Hash;
+
+use hash::BuildMerkleHasher;
+use node::HashValue;
+use tree::{MerkleTree, Proof, ProofError, hash_pair, height_for};
+use hex::ct_eq;
+
+
+// Proofs against earlier sizes of an append-only tree.
+//
+// A tree that only ever grows can answer for any size it had before:
+//  the tree of the first `size` leaves shares every node whose leaves
+//  all come before `size`, only its right edge differs. subtree_hash()
+//  rebuilds that edge, which is O(log n) nodes.
+//
+// inclusion_proof() gives the proof a leaf had in the tree of a given
+//  size, check it with verify_at() against that size's root.
+//
+// A consistency proof from `old_size` to `new_size` is the list of
+//  hashes of perfect subtrees that cover [0, old_size) and then
+//  [old_size, new_size), both greedily from the left (see cover()).
+//  The first part alone rebuilds the old root, all of it the new one,
+//  so the new tree holds the old one's leaves unchanged.
+
+impl<V, S> MerkleTree<V, S>
+where
+    V: Hash + Clone,
+    S: BuildMerkleHasher
+{
+    // Hash of the node at (level, index) in the tree of the first
+    //  `size` leaves, None if there's no such node
+    pub fn subtree_hash(&self, size: usize, level: usize, index: usize) -> Option<HashValue<S>> {
+        let start = index.checked_shl(level as u32)?;
+        if size > self.leaf_count().unwrap_or(0) || start >= size || level > height_for(size) {
+            return None
+        }
+        let end = start + (1 << level);
+        if end <= size {
+            return self.node_at(level, index).map(|node| node.hash_value())
+        }
+        // Right edge of the old tree: rebuild it from its children
+        let left = self.subtree_hash(size, level - 1, 2 * index)?;
+        let right = match self.subtree_hash(size, level - 1, 2 * index + 1) {
+            Some(right) => right,
+            None => left.clone()
+        };
+        Some(hash_pair(&left, &right, &self.hasher_builder()))
+    }
+
+    pub fn root_hash_at(&self, size: usize) -> Option<HashValue<S>> {
+        self.subtree_hash(size, height_for(size), 0)
+    }
+
+    // Proof the leaf at `index` had when the tree had `size` leaves,
+    //  in the same form index_proof() gives for the current size
+    pub fn inclusion_proof(&self, index: usize, size: usize) -> Option<Vec<Proof<HashValue<S>>>> {
+        if index >= size || size > self.leaf_count().unwrap_or(0) {
+            return None
+        }
+        let mut proof = Vec::with_capacity(height_for(size));
+        for level in 0..height_for(size) {
+            let position = index >> level;
+            if position & 1 == 1 {
+                proof.push(Proof::Left(self.subtree_hash(size, level, position - 1)?));
+            } else {
+                // Siblingless nodes are paired with themselves
+                let sibling = self.subtree_hash(size, level, position + 1)
+                    .or_else(|| self.subtree_hash(size, level, position))?;
+                proof.push(Proof::Right(sibling));
+            }
+        }
+        Some(proof)
+    }
+
+    pub fn consistency_proof(&self, old_size: usize, new_size: usize) -> Option<Vec<HashValue<S>>> {
+        if old_size == 0 || old_size > new_size || new_size > self.leaf_count().unwrap_or(0) {
+            return None
+        }
+        consistency_blocks(old_size, new_size).into_iter()
+            .map(|(level, index)| self.node_at(level, index).map(|node| node.hash_value()))
+            .collect()
+    }
+}
+
+pub fn verify_consistency<S>(
+    old_size: usize,
+    old_root: &HashValue<S>,
+    new_size: usize,
+    new_root: &HashValue<S>,
+    proof: &[HashValue<S>],
+    hasher_builder: &S) -> Result<(), ProofError>
+where
+    S: BuildMerkleHasher
+{
+    if old_size == 0 || old_size > new_size {
+        return Err(ProofError::IndexOutOfRange { index: old_size, leaf_count: new_size })
+    }
+    let blocks = consistency_blocks(old_size, new_size);
+    if proof.len() != blocks.len() {
+        return Err(ProofError::WrongLength { expected: blocks.len(), found: proof.len() })
+    }
+    let old_count = cover(0, old_size).len();
+    let old = &proof[..old_count];
+    if !ct_eq(root_from_cover(old, &blocks[..old_count], old_size, hasher_builder).as_ref(), old_root.as_ref()) {
+        return Err(ProofError::RootMismatch)
+    }
+    if !ct_eq(root_from_cover(proof, &blocks, new_size, hasher_builder).as_ref(), new_root.as_ref()) {
+        return Err(ProofError::RootMismatch)
+    }
+    Ok(())
+}
+
+// (level, index) of the perfect subtrees in a consistency proof
+fn consistency_blocks(old_size: usize, new_size: usize) -> Vec<(usize, usize)> {
+    let mut blocks = cover(0, old_size);
+    blocks.extend(cover(old_size, new_size));
+    blocks
+}
+
+// Covers [start, end) with perfect subtrees, each as big as its
+//  position and the end allow. Sizes are checked so that subtrees of
+//  2^63 leaves and more don't wrap around.
+fn cover(start: usize, end: usize) -> Vec<(usize, usize)> {
+    let mut blocks = vec![];
+    let mut position = start;
+    while position < end {
+        let mut level = 0;
+        while let Some(size) = 1usize.checked_shl(level as u32 + 1) {
+            if position % size != 0 || end - position < size {
+                break
+            }
+            level += 1;
+        }
+        blocks.push((level, position >> level));
+        position += 1 << level;
+    }
+    blocks
+}
+
+// Root of the tree of `size` leaves from hashes of subtrees covering
+//  [0, size) in order, `blocks` says where each one sits
+fn root_from_cover<S>(hashes: &[HashValue<S>], blocks: &[(usize, usize)], size: usize, hasher_builder: &S) -> HashValue<S>
+where
+    S: BuildMerkleHasher
+{
+    let mut next = 0;
+    node_from_cover(hashes, blocks, &mut next, size, height_for(size), 0, hasher_builder)
+}
+
+fn node_from_cover<S>(
+    hashes: &[HashValue<S>],
+    blocks: &[(usize, usize)],
+    next: &mut usize,
+    size: usize,
+    level: usize,
+    index: usize,
+    hasher_builder: &S) -> HashValue<S>
+where
+    S: BuildMerkleHasher
+{
+    if blocks[*next] == (level, index) {
+        *next += 1;
+        return hashes[*next - 1].clone()
+    }
+    // Blocks tile [0, size) with aligned subtrees, so a node that isn't
+    //  a block is made of several and can't be a leaf
+    let left = node_from_cover(hashes, blocks, next, size, level - 1, 2 * index, hasher_builder);
+    let right = if (2 * index + 1) << (level - 1) < size {
+        node_from_cover(hashes, blocks, next, size, level - 1, 2 * index + 1, hasher_builder)
+    } else {
+        left.clone()
+    };
+    hash_pair(&left, &right, hasher_builder)
+}
@@ -0,0 +1,58 @@
+use sha256::Sha256;
+use sth::{Signer, Verifier};
+use hex::ct_eq;
+
+
+// HMAC-SHA256 (RFC 2104) as a Signer and Verifier for tree heads.
+//
+// It is a shared-key scheme: whoever can check a signature holds the
+//  key and can sign heads as well. That fits a log whose monitors are
+//  trusted with the key, not a public one; use a public-key Signer
+//  for that. Keys longer than the 64-byte block are hashed first.
+
+#[derive(Clone)]
+pub struct HmacSha256 {
+    key: [u8; 64]
+}
+
+impl HmacSha256 {
+    pub fn new(key: &[u8]) -> HmacSha256 {
+        let mut padded = [0u8; 64];
+        if key.len() > 64 {
+            padded[..32].copy_from_slice(&Sha256::digest(key));
+        } else {
+            padded[..key.len()].copy_from_slice(key);
+        }
+        HmacSha256 { key: padded }
+    }
+
+    pub fn mac(&self, data: &[u8]) -> [u8; 32] {
+        let mut inner = Sha256::new();
+        inner.update(&self.padded_key(0x36));
+        inner.update(data);
+        let mut outer = Sha256::new();
+        outer.update(&self.padded_key(0x5c));
+        outer.update(&inner.finalize());
+        outer.finalize()
+    }
+
+    fn padded_key(&self, pad: u8) -> [u8; 64] {
+        let mut padded = self.key;
+        for byte in padded.iter_mut() {
+            *byte ^= pad;
+        }
+        padded
+    }
+}
+
+impl Signer for HmacSha256 {
+    fn sign(&self, data: &[u8]) -> Vec<u8> {
+        self.mac(data).to_vec()
+    }
+}
+
+impl Verifier for HmacSha256 {
+    fn verify(&self, data: &[u8], signature: &[u8]) -> bool {
+        ct_eq(&self.mac(data), signature)
+    }
+}
@@ -0,0 +1,171 @@
+use std::cmp::min;
+use std::hash::Hasher;
+
+use hash::{MerkleHasher, BuildMerkleHasher};
+
+
+// Keccak-256 as a MerkleHasher, the hash Ethereum calls keccak256.
+//
+// This is the original Keccak submission, not FIPS 202 SHA3-256:
+//  the two only differ in the padding byte (0x01 here, 0x06 there),
+//  but that changes every output.
+//
+// Unlike BuildSha256, BuildKeccak256 hashes branches over the raw
+//  bytes of the two hashes, without the length prefixes Hash puts in
+//  front of slices, so a branch is keccak256(abi.encodePacked(a, b))
+//  as Solidity computes it.
+// Leaves inserted as values still go through Hash, so the leaf of a
+//  Vec<u8> is not keccak256 of its bytes. For leaves that match
+//  Ethereum's, hash them with Keccak256::digest() and insert the
+//  hashes into a HashTree with insert_hash() or insert_hashes().
+
+const RATE: usize = 136;
+
+const ROUND_CONSTANTS: [u64; 24] = [
+    0x0000000000000001, 0x0000000000008082, 0x800000000000808a, 0x8000000080008000,
+    0x000000000000808b, 0x0000000080000001, 0x8000000080008081, 0x8000000000008009,
+    0x000000000000008a, 0x0000000000000088, 0x0000000080008009, 0x000000008000000a,
+    0x000000008000808b, 0x800000000000008b, 0x8000000000008089, 0x8000000000008003,
+    0x8000000000008002, 0x8000000000000080, 0x000000000000800a, 0x800000008000000a,
+    0x8000000080008081, 0x8000000000008080, 0x0000000080000001, 0x8000000080008008
+];
+
+// Rotation of each lane, indexed by x + 5 * y
+const ROTATIONS: [u32; 25] = [
+    0, 1, 62, 28, 27,
+    36, 44, 6, 55, 20,
+    3, 10, 43, 25, 39,
+    41, 45, 15, 21, 8,
+    18, 2, 61, 56, 14
+];
+
+#[derive(Clone)]
+pub struct Keccak256 {
+    state: [u64; 25],
+    buffer: [u8; RATE],
+    buffered: usize
+}
+
+#[derive(Clone, Default)]
+pub struct BuildKeccak256;
+
+impl Keccak256 {
+    pub fn new() -> Keccak256 {
+        Keccak256 {
+            state: [0; 25],
+            buffer: [0; RATE],
+            buffered: 0
+        }
+    }
+
+    pub fn digest(data: &[u8]) -> [u8; 32] {
+        let mut hasher = Keccak256::new();
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let taken = min(RATE - self.buffered, data.len());
+            self.buffer[self.buffered..self.buffered + taken].copy_from_slice(&data[..taken]);
+            self.buffered += taken;
+            data = &data[taken..];
+            if self.buffered == RATE {
+                self.absorb();
+            }
+        }
+    }
+
+    pub fn finalize(mut self) -> [u8; 32] {
+        for byte in self.buffer[self.buffered..].iter_mut() {
+            *byte = 0;
+        }
+        self.buffer[self.buffered] ^= 0x01;
+        self.buffer[RATE - 1] ^= 0x80;
+        self.absorb();
+
+        let mut digest = [0u8; 32];
+        for (chunk, lane) in digest.chunks_mut(8).zip(self.state.iter()) {
+            chunk.copy_from_slice(&lane.to_le_bytes());
+        }
+        digest
+    }
+
+    fn absorb(&mut self) {
+        for (lane, chunk) in self.state.iter_mut().zip(self.buffer.chunks(8)) {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(chunk);
+            *lane ^= u64::from_le_bytes(bytes);
+        }
+        self.buffered = 0;
+        keccak_f(&mut self.state);
+    }
+}
+
+// Keccak-f[1600], lanes indexed by x + 5 * y
+fn keccak_f(a: &mut [u64; 25]) {
+    for round_constant in ROUND_CONSTANTS.iter() {
+        // θ
+        let mut c = [0u64; 5];
+        for x in 0..5 {
+            c[x] = a[x] ^ a[x + 5] ^ a[x + 10] ^ a[x + 15] ^ a[x + 20];
+        }
+        for x in 0..5 {
+            let d = c[(x + 4) % 5] ^ c[(x + 1) % 5].rotate_left(1);
+            for y in 0..5 {
+                a[x + 5 * y] ^= d;
+            }
+        }
+        // ρ and π
+        let mut b = [0u64; 25];
+        for x in 0..5 {
+            for y in 0..5 {
+                b[y + 5 * ((2 * x + 3 * y) % 5)] = a[x + 5 * y].rotate_left(ROTATIONS[x + 5 * y]);
+            }
+        }
+        // χ
+        for x in 0..5 {
+            for y in 0..5 {
+                a[x + 5 * y] = b[x + 5 * y] ^ (!b[(x + 1) % 5 + 5 * y] & b[(x + 2) % 5 + 5 * y]);
+            }
+        }
+        // ι
+        a[0] ^= round_constant;
+    }
+}
+
+impl Hasher for Keccak256 {
+    fn finish(&self) -> u64 {
+        let digest = self.finish_full();
+        let mut head = [0u8; 8];
+        head.copy_from_slice(&digest[..8]);
+        u64::from_be_bytes(head)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.update(bytes)
+    }
+}
+
+impl MerkleHasher for Keccak256 {
+    type Output = [u8; 32];
+
+    fn finish_full(&self) -> [u8; 32] {
+        self.clone().finalize()
+    }
+}
+
+impl BuildMerkleHasher for BuildKeccak256 {
+    type Hasher = Keccak256;
+
+    fn build_hasher(&self) -> Keccak256 {
+        Keccak256::new()
+    }
+
+    fn hash_branch(&self, left: &[u8], right: &[u8]) -> [u8; 32] {
+        let mut hasher = Keccak256::new();
+        hasher.update(left);
+        hasher.update(right);
+        hasher.finalize()
+    }
+}
@@ -1,28 +1,84 @@
-#![feature(box_patterns)]
 #![allow(dead_code)]
-#![feature(slice_patterns)]
-#![feature(advanced_slice_patterns)]
-mod hash;
-mod node;
-mod tree;
+#![cfg_attr(feature = "bench", feature(test))]
+pub mod hash;
+pub mod node;
+pub mod tree;
+pub mod diff;
+pub mod codec;
+pub mod sync;
+pub mod sha256;
+pub mod keccak;
+pub mod manifest;
+pub mod chunks;
+pub mod export;
+pub mod pretty;
+pub mod hex;
+pub mod history;
+pub mod sth;
+pub mod hmac;
+pub mod log;
+pub mod bitcoin;
+pub mod openzeppelin;
+pub mod rlp;
+pub mod patricia;
+pub mod rfc6962;
+#[cfg(feature = "server")]
+pub mod server;
 
 #[cfg(test)]
 mod tests {
-    use std::mem::transmute;
     use std::collections::hash_map::DefaultHasher;
     use std::hash::{BuildHasherDefault, Hasher, Hash};
+    use std::sync::mpsc::channel;
+    use std::thread;
 
-    use tree::{MerkleTree, Proof, prove};
-    use hash::{MerkleHasher, BuildMerkleHasher};
+    use tree;
+    use tree::{MerkleTree, HashTree, Proof, ProofError, DuplicatePolicy, InsertError, prove, verify, verify_at, verify_subtree};
+    use sha256::{Sha256, BuildSha256};
+    use keccak::{Keccak256, BuildKeccak256};
+    use manifest::{self, Change};
+    use chunks::{ChunkedTree, ChunkVerifier};
+    use export::{to_dot, to_json};
+    use pretty::{pretty, PrettyOptions};
+    use hex::{Hex, HexError, to_hex, from_hex, ct_eq};
+    use history::verify_consistency;
+    use sth::{SignedTreeHead, SthError, Signer, Verifier};
+    use hmac::HmacSha256;
+    use bitcoin::{self, MerkleBlock, PartialMerkleTree, PartialTreeError};
+    use openzeppelin::{self, SortedPairs, MultiProof};
+    use rlp::{self, Rlp};
+    use patricia::{self, PatriciaTrie, TrieError};
+    use rfc6962::{Rfc6962, Entry};
+    use log::TransparencyLog;
+    use hash::{MerkleHasher, BuildMerkleHasher, Digest};
+    use diff::diff;
+    use sync::{self, Record, Message, NodeHash, LeafData, Reconciler, SyncError, respond};
+    use codec::DecodeError;
 
 
     #[derive(Clone, Hash)]
     struct TestStruct(u64);
 
+    impl Record for TestStruct {
+        fn to_bytes(&self) -> Vec<u8> {
+            self.0.to_be_bytes().to_vec()
+        }
+
+        fn from_bytes(bytes: &[u8]) -> Option<TestStruct> {
+            if bytes.len() != 8 {
+                return None
+            }
+            let mut buf = [0u8; 8];
+            buf.copy_from_slice(bytes);
+            Some(TestStruct(u64::from_be_bytes(buf)))
+        }
+    }
+
     impl MerkleHasher for DefaultHasher {
-        fn finish_full(&self) -> Box<[u8]> {
-            let slice: [u8;8] = unsafe { transmute((*self).finish().to_be()) };
-            Box::new(slice)
+        type Output = [u8; 8];
+
+        fn finish_full(&self) -> [u8; 8] {
+            self.finish().to_be_bytes()
         }
     }
 
@@ -45,16 +101,28 @@ mod tests {
         tree
     }
 
+    fn make_tree_of(values: Vec<u64>) -> MerkleTree<TestStruct, BuildHasherDefault<DefaultHasher>> {
+        let mut tree = MerkleTree::with_hasher(BuildHasherDefault::default());
+        tree.insert_items(values.into_iter().map(TestStruct));
+        tree
+    }
+
     #[test]
     fn test_height() {
         let small_tree = make_small_tree();        
         let tree = make_tree();        
         println!("Small tree height = {}", small_tree.height());
-        println!("{}", small_tree);
+        println!("{:?}", small_tree);
         assert!(small_tree.height() == 1); 
         println!("Tree height = {}", tree.height());
-        println!("{}", tree);              
+        println!("{:?}", tree);              
         assert!(tree.height() == 3);
+
+        let heights = [(0, 0), (1, 0), (2, 1), (3, 2), (4, 2), (5, 3), (1 << 40, 40), ((1 << 40) + 1, 41)];
+        for &(leaf_count, height) in heights.iter() {
+            assert!(tree::height_for(leaf_count) == height);
+        }
+        assert!(tree::height_for(usize::MAX) == usize::BITS as usize);
     }
 
     #[test]
@@ -75,25 +143,11 @@ mod tests {
         let mut second = make_tree();
         let first_hash = first.root_hash().unwrap();
         let mut second_hash = second.root_hash().unwrap();
-        for &byte in first_hash.as_ref() {
-            print!("{:X}", byte);
-        }
-        print!(" vs ");
-        for &byte in second_hash.as_ref() {
-            print!("{:X}", byte);
-        }
-        println!();
+        println!("{} vs {}", Hex(first_hash), Hex(second_hash));
         assert!(first_hash == second_hash);
         second.insert(TestStruct(0));
         second_hash = second.root_hash().unwrap();
-        for &byte in first_hash.as_ref() {
-            print!("{:X}", byte);
-        }
-        print!(" vs ");
-        for &byte in second_hash.as_ref() {
-            print!("{:X}", byte);
-        }
-        println!();
+        println!("{} vs {}", Hex(first_hash), Hex(second_hash));
         assert!(first_hash != second_hash);
     }
 
@@ -108,4 +162,1365 @@ mod tests {
         assert!(proof.len() == tree.height());
         assert!(prove(&target_hash, &proof, &tree))
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_diff_equal_trees() {
+        let first = make_tree();
+        let second = make_tree();
+        assert!(diff(&first, &second).is_empty());
+        let empty = make_tree_of(vec![]);
+        assert!(diff(&empty, &empty).is_empty());
+    }
+
+    #[test]
+    fn test_diff_changed_leaves() {
+        let first = make_tree();
+        let second = make_tree_of(vec![0, 1, 2, 9, 4, 8, 6]);
+        assert!(diff(&first, &second) == vec![3..4, 5..6]);
+        let third = make_tree_of(vec![0, 1, 2, 3, 9, 9, 6]);
+        assert!(diff(&first, &third) == vec![4..6]);
+    }
+
+    #[test]
+    fn test_diff_different_sizes() {
+        let first = make_tree();
+        let second = make_tree_of((0..12).collect());
+        assert!(diff(&first, &second) == vec![7..12]);
+        assert!(diff(&second, &first) == vec![7..12]);
+        let third = make_tree_of(vec![0, 1, 5]);
+        assert!(diff(&first, &third) == vec![2..7]);
+        let empty = make_tree_of(vec![]);
+        assert!(diff(&empty, &first) == vec![0..7]);
+    }
+
+    #[test]
+    fn test_diff_duplicated_sibling() {
+        // [0, 1, 2] hashes its lonely leaf twice, just like [0, 1, 2, 2] does
+        let first = make_tree_of(vec![0, 1, 2]);
+        let second = make_tree_of(vec![0, 1, 2, 2]);
+        assert!(first.root_hash() == second.root_hash());
+        assert!(diff(&first, &second) == vec![3..4]);
+    }
+
+    #[test]
+    fn test_node_at() {
+        let tree = make_tree();
+        assert!(tree.node_at(3, 0).unwrap().hash_value() == tree.root_hash().unwrap());
+        assert!(tree.node_at(2, 1).unwrap().leaf_count() == 3);
+        assert!(tree.node_at(1, 3).unwrap().leaf_count() == 1);
+        assert!(tree.node_at(0, 7).is_none());
+        assert!(tree.node_at(3, 1).is_none());
+        assert!(tree.node_at(4, 0).is_none());
+        assert!(tree.leaf(5).unwrap().0 == 5);
+    }
+
+    #[test]
+    fn test_sync_message_roundtrip() {
+        let messages = vec![
+            Message::GetRoot,
+            Message::Root { leaf_count: 7, hash: vec![1, 2, 3] },
+            Message::GetHashes { level: 2, indices: vec![0, 1] },
+            Message::Hashes { level: 2, nodes: vec![NodeHash { index: 1, leaf_count: 3, hash: vec![4] }] },
+            Message::GetLeaves { ranges: vec![3..4, 7..12] },
+            Message::Leaves { leaves: vec![(3, LeafData::Value(vec![0; 8])), (7, LeafData::Hash(vec![]))] }
+        ];
+        for message in messages {
+            let encoded = message.encode();
+            assert!(Message::decode(&encoded) == Ok(message));
+            assert!(Message::decode(&encoded[..encoded.len() - 1]).is_err());
+        }
+    }
+
+    fn sync_over_channels(
+        local: &mut MerkleTree<TestStruct, BuildHasherDefault<DefaultHasher>>,
+        remote: MerkleTree<TestStruct, BuildHasherDefault<DefaultHasher>>) -> usize
+    {
+        let (to_remote, remote_inbox) = channel::<Vec<u8>>();
+        let (to_local, local_inbox) = channel::<Vec<u8>>();
+        let responder = thread::spawn(move || {
+            let mut sent_leaves = 0;
+            for bytes in remote_inbox {
+                let request = Message::decode(&bytes).unwrap();
+                if let Some(response) = respond(&remote, &request) {
+                    if let Message::Leaves { ref leaves } = response {
+                        sent_leaves += leaves.len();
+                    }
+                    to_local.send(response.encode()).unwrap();
+                }
+            }
+            sent_leaves
+        });
+
+        {
+            let mut reconciler = Reconciler::new(local);
+            to_remote.send(reconciler.start().encode()).unwrap();
+            loop {
+                let response = Message::decode(&local_inbox.recv().unwrap()).unwrap();
+                match reconciler.receive(response).unwrap() {
+                    Some(request) => to_remote.send(request.encode()).unwrap(),
+                    None => break
+                }
+            }
+        }
+        drop(to_remote);
+        responder.join().unwrap()
+    }
+
+    #[test]
+    fn test_sync_replicas() {
+        let mut local = make_tree();
+        let remote = make_tree_of(vec![0, 1, 9, 3, 4, 5, 6, 7, 8, 9, 10, 11]);
+        let remote_hash = remote.root_hash();
+        assert!(sync_over_channels(&mut local, remote) == 6);
+        assert!(local.root_hash() == remote_hash);
+        assert!(local.leaf_count() == Some(12));
+
+        let remote = make_tree_of(vec![0, 1, 9, 3, 4, 5, 6, 7, 8, 9, 10, 11]);
+        assert!(sync_over_channels(&mut local, remote) == 0);
+        assert!(local.root_hash() == remote_hash);
+
+        let mut single = make_tree_of(vec![0]);
+        let remote = make_tree_of(vec![0, 1, 9, 3, 4, 5, 6, 7, 8, 9, 10, 11]);
+        assert!(sync_over_channels(&mut single, remote) == 11);
+        assert!(single.root_hash() == remote_hash);
+    }
+
+    #[test]
+    fn test_sync_shrinks_and_fills_empty() {
+        let mut local = make_tree_of((0..10).collect());
+        let remote = make_tree();
+        let remote_hash = remote.root_hash();
+        assert!(sync_over_channels(&mut local, remote) == 0);
+        assert!(local.root_hash() == remote_hash);
+
+        let mut empty = make_tree_of(vec![]);
+        assert!(sync_over_channels(&mut empty, make_tree()) == 7);
+        assert!(empty.root_hash() == remote_hash);
+    }
+
+    #[test]
+    fn test_sync_leaves_in_batches() {
+        let request = Message::GetLeaves { ranges: vec![5..u64::MAX, 0..1] };
+        match respond(&make_tree(), &request) {
+            Some(Message::Leaves { leaves }) => {
+                assert!(leaves.iter().map(|&(index, _)| index).collect::<Vec<_>>() == vec![5, 6, 0]);
+            },
+            _ => panic!("expected leaves")
+        }
+
+        let count = sync::MAX_LEAVES_PER_RESPONSE as u64 * 2 + 5;
+        let remote = make_tree_of((0..count).collect());
+        let remote_hash = remote.root_hash();
+        let mut local = make_tree_of(vec![0, 1, 2]);
+        assert!(sync_over_channels(&mut local, remote) == count as usize - 3);
+        assert!(local.root_hash() == remote_hash);
+    }
+
+    fn sync_directly(
+        local: &mut MerkleTree<TestStruct, BuildHasherDefault<DefaultHasher>>,
+        remote: &MerkleTree<TestStruct, BuildHasherDefault<DefaultHasher>>) -> Result<(), SyncError>
+    {
+        let mut reconciler = Reconciler::new(local);
+        let mut request = reconciler.start();
+        while let Some(next) = reconciler.receive(respond(remote, &request).unwrap())? {
+            request = next;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_keeps_duplicate_policy() {
+        let mut local = make_tree_of(vec![0, 1, 2]);
+        local.set_duplicate_policy(DuplicatePolicy::Reject);
+        let remote = make_tree_of(vec![0, 1, 7, 3, 4, 5]);
+        assert!(sync_directly(&mut local, &remote) == Ok(()));
+        assert!(local.root_hash() == remote.root_hash());
+        assert!(local.duplicate_policy() == DuplicatePolicy::Reject);
+        assert!(local.leaf_positions(&leaf_hash(7)) == vec![2]);
+        assert!(local.try_insert(TestStruct(4)) == Err(InsertError::Duplicate(4)));
+
+        let with_duplicates = make_tree_of(vec![0, 1, 7, 3, 4, 5, 1]);
+        assert!(sync_directly(&mut local, &with_duplicates) == Err(SyncError::DuplicateLeaf(1)));
+        assert!(local.root_hash() == remote.root_hash());
+        assert!(local.leaf_positions(&leaf_hash(5)) == vec![5]);
+    }
+
+    #[test]
+    fn test_sync_rejects_bad_responses() {
+        let mut local = make_tree();
+        let local_hash = local.root_hash();
+        {
+            let mut reconciler = Reconciler::new(&mut local);
+            let root = Message::Root { leaf_count: sync::MAX_LEAVES + 1, hash: vec![0; 8] };
+            assert!(reconciler.receive(root) == Err(SyncError::TooManyLeaves(sync::MAX_LEAVES + 1)));
+        }
+
+        let remote = make_tree_of((10..22).collect());
+        let node = |index, leaf_count| NodeHash { index: index, leaf_count: leaf_count, hash: vec![0; 8] };
+        let hashes = |level, nodes| Message::Hashes { level: level, nodes: nodes };
+        let bad_responses = vec![
+            (hashes(3, vec![node(0, 8)]), SyncError::UnexpectedLevel(3)),
+            (hashes(70, vec![node(0, 8)]), SyncError::UnexpectedLevel(70)),
+            (hashes(2, vec![node(3, 4)]), SyncError::BadNode(3)),
+            (hashes(2, vec![node(1, 5)]), SyncError::BadNode(1)),
+            (hashes(2, vec![node(2, 0)]), SyncError::BadNode(2)),
+            (hashes(2, vec![node(u64::MAX, 4)]), SyncError::BadNode(u64::MAX))
+        ];
+        for (response, error) in bad_responses {
+            let mut reconciler = Reconciler::new(&mut local);
+            assert!(reconciler.receive(hashes(0, vec![])) == Err(SyncError::UnexpectedLevel(0)));
+            let request = respond(&remote, &reconciler.start()).unwrap();
+            let request = reconciler.receive(request).unwrap().unwrap();
+            assert!(request == Message::GetHashes { level: 3, indices: vec![0] });
+            let request = reconciler.receive(respond(&remote, &request).unwrap()).unwrap().unwrap();
+            assert!(request == Message::GetHashes { level: 2, indices: vec![0, 1] });
+            assert!(reconciler.receive(response) == Err(error));
+        }
+        assert!(local.root_hash() == local_hash);
+    }
+
+    fn leaf_hash(value: u64) -> [u8; 8] {
+        tree::leaf_hash(&TestStruct(value), &BuildHasherDefault::<DefaultHasher>::default())
+    }
+
+    fn make_hash_tree_of(values: Vec<u64>) -> HashTree<BuildHasherDefault<DefaultHasher>> {
+        let mut tree = MerkleTree::with_hasher(BuildHasherDefault::default());
+        tree.insert_hashes(values.into_iter().map(leaf_hash));
+        tree
+    }
+
+    #[test]
+    fn test_hash_leaves() {
+        let tree = make_tree();
+        let hash_tree = make_hash_tree_of((0..7).collect());
+        assert!(hash_tree.root_hash() == tree.root_hash());
+        assert!(hash_tree.node_at(0, 3).unwrap().value().is_none());
+
+        let mut mixed = make_tree_of(vec![0, 1, 2]);
+        mixed.insert_hash(leaf_hash(3));
+        mixed.insert_items(vec![TestStruct(4), TestStruct(5)]);
+        mixed.insert_hash(leaf_hash(6));
+        assert!(mixed.root_hash() == tree.root_hash());
+        assert!(mixed.leaf(2).unwrap().0 == 2);
+        assert!(mixed.leaf(3).is_none());
+    }
+
+    #[test]
+    fn test_hash_proof() {
+        let tree = make_hash_tree_of((0..7).collect());
+        let target = leaf_hash(5);
+        let proof = tree.hash_proof(&target);
+        assert!(proof.len() == tree.height());
+        assert!(prove(&target, &proof, &tree));
+        assert!(tree.hash_proof(&leaf_hash(9)).is_empty());
+    }
+
+    #[test]
+    fn test_sync_hash_leaves() {
+        let (to_remote, remote_inbox) = channel::<Vec<u8>>();
+        let (to_local, local_inbox) = channel::<Vec<u8>>();
+        let remote = make_hash_tree_of((0..9).collect());
+        let remote_hash = remote.root_hash();
+        let responder = thread::spawn(move || {
+            for bytes in remote_inbox {
+                let response = respond(&remote, &Message::decode(&bytes).unwrap()).unwrap();
+                to_local.send(response.encode()).unwrap();
+            }
+        });
+
+        let mut local = make_hash_tree_of(vec![0, 1, 2, 3]);
+        {
+            let mut reconciler = Reconciler::new(&mut local);
+            let mut request = reconciler.start();
+            loop {
+                to_remote.send(request.encode()).unwrap();
+                let response = Message::decode(&local_inbox.recv().unwrap()).unwrap();
+                match reconciler.receive(response).unwrap() {
+                    Some(next) => request = next,
+                    None => break
+                }
+            }
+        }
+        drop(to_remote);
+        responder.join().unwrap();
+        assert!(local.root_hash() == remote_hash);
+    }
+
+    #[test]
+    fn test_parallel_build_matches_sequential() {
+        for &count in &[0u64, 1, 7, 1500, 5000] {
+            let sequential = make_tree_of((0..count).collect());
+            let mut parallel = MerkleTree::with_hasher(BuildHasherDefault::default());
+            parallel.insert_items_parallel((0..count).map(TestStruct), 4);
+            assert!(parallel.root_hash() == sequential.root_hash());
+            assert!(parallel.leaf_count() == sequential.leaf_count());
+        }
+
+        let mut incremental = make_tree();
+        incremental.insert_items_parallel((7..3000).map(TestStruct), 0);
+        incremental.insert_items_parallel((3000..3001).map(TestStruct), 3);
+        assert!(incremental.root_hash() == make_tree_of((0..3001).collect()).root_hash());
+    }
+
+    #[test]
+    fn test_insert_one_by_one_matches_bulk() {
+        let mut tree = make_tree_of(vec![]);
+        for value in 0..40 {
+            tree.insert(TestStruct(value));
+            let bulk = make_tree_of((0..value + 1).collect());
+            assert!(tree.root_hash() == bulk.root_hash());
+            assert!(tree.height() == bulk.height());
+            assert!(tree.leaf_count() == bulk.leaf_count());
+        }
+    }
+
+    #[test]
+    fn test_index_proof() {
+        for count in 1..20 {
+            let tree = make_tree_of((0..count).collect());
+            for index in 0..count {
+                let proof = tree.index_proof(index as usize).unwrap();
+                assert!(proof.len() == tree.height());
+                assert!(prove(&leaf_hash(index), &proof, &tree));
+                assert!(!prove(&leaf_hash(index + 1), &proof, &tree));
+            }
+            assert!(tree.index_proof(count as usize).is_none());
+        }
+    }
+
+    #[test]
+    fn test_value_proof_every_leaf() {
+        let tree = make_tree();
+        for value in 0..7 {
+            let proof = tree.value_proof(&TestStruct(value));
+            assert!(proof.len() == tree.height());
+            assert!(prove(&leaf_hash(value), &proof, &tree));
+        }
+        assert!(tree.value_proof(&TestStruct(7)).is_empty());
+    }
+
+    #[test]
+    fn test_digest_from_bytes() {
+        let hash = leaf_hash(3);
+        assert!(<[u8; 8]>::from_bytes(&hash) == Some(hash));
+        assert!(<[u8; 8]>::from_bytes(&hash[1..]).is_none());
+        assert!(<[u8; 4]>::from_bytes(&hash).is_none());
+        assert!(<Box<[u8]>>::from_bytes(&hash[1..]).unwrap().len() == 7);
+    }
+
+    #[test]
+    fn test_sha256() {
+        assert!(to_hex(&Sha256::digest(b"")) == "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert!(to_hex(&Sha256::digest(b"abc")) == "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        let long = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+        assert!(to_hex(&Sha256::digest(long)) == "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1");
+
+        let million = vec![b'a'; 1_000_000];
+        let mut hasher = BuildSha256.build_hasher();
+        for chunk in million.chunks(997) {
+            hasher.write(chunk);
+        }
+        assert!(to_hex(&hasher.finish_full()) == "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0");
+
+        // Branches hash raw child hashes, records hash as the CLI does
+        assert!(BuildSha256.hash_branch(b"ab", b"c") == Sha256::digest(b"abc"));
+        let mut tree = HashTree::with_hasher(BuildSha256);
+        tree.insert_hashes([&b"a"[..], b"b", b"c"].iter().map(|record| {
+            let mut hasher = Sha256::new();
+            hasher.update(&(record.len() as u64).to_be_bytes());
+            hasher.update(record);
+            hasher.finalize()
+        }));
+        assert!(to_hex(&tree.root_hash().unwrap()) == "fd8f2c9a495a97c8e4a690f083d937048837e20d480d0a1d936391a1999958a9");
+    }
+
+    #[test]
+    fn test_verify_against_root() {
+        let mut tree = MerkleTree::with_hasher(BuildSha256);
+        tree.insert_items((0..11u64).map(|value| value.to_be_bytes().to_vec()));
+        let root = tree.root_hash().unwrap();
+        let target = tree::leaf_hash(&7u64.to_be_bytes().to_vec(), &BuildSha256);
+        let proof = tree.index_proof(7).unwrap();
+        assert!(verify(&target, &proof, &root, &BuildSha256));
+        assert!(!verify(&target, &proof[1..], &root, &BuildSha256));
+        assert!(tree.value_proof(&7u64.to_be_bytes().to_vec()).len() == proof.len());
+    }
+
+    #[test]
+    fn test_manifest() {
+        use std::fs;
+
+        let dir = ::std::env::temp_dir().join(format!("schmerkle-manifest-{}", ::std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("b/c")).unwrap();
+        fs::write(dir.join("a.txt"), b"first").unwrap();
+        fs::write(dir.join("b/c/d.txt"), b"second").unwrap();
+        fs::write(dir.join("b/e.txt"), vec![7u8; 200_000]).unwrap();
+
+        let before = manifest::hash_dir(&dir, &BuildSha256).unwrap();
+        let names: Vec<&str> = before.files.iter().map(|file| file.0.as_str()).collect();
+        assert!(names == vec!["a.txt", "b/c/d.txt", "b/e.txt"]);
+        let mut hasher = BuildSha256.build_hasher();
+        hasher.write(&7u64.to_be_bytes());
+        hasher.write(b"b/e.txt");
+        hasher.write(&200_000u64.to_be_bytes());
+        hasher.write(&[7u8; 200_000]);
+        let expected = hasher.finish_full();
+        assert!(before.files[2].1 == expected);
+        assert!(to_hex(&before.root.unwrap()) == "638ac0b8fccd749b53c2fa8a4ac5a2044a0d7cf4608661c48974ce3ec805581b");
+        assert!(before.changes(&manifest::hash_dir(&dir, &BuildSha256).unwrap()).is_empty());
+
+        fs::write(dir.join("b/c/d.txt"), b"changed").unwrap();
+        fs::remove_file(dir.join("a.txt")).unwrap();
+        fs::write(dir.join("f.txt"), b"new").unwrap();
+        let after = manifest::hash_dir(&dir, &BuildSha256).unwrap();
+        assert!(after.root != before.root);
+        assert!(before.changes(&after) == vec![
+            Change::Removed("a.txt".to_string()),
+            Change::Modified("b/c/d.txt".to_string()),
+            Change::Added("f.txt".to_string())
+        ]);
+        assert!(manifest::from_files(after.files.clone(), &BuildSha256).root == after.root);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_chunk_proofs() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i * 7) as u8).collect();
+        let chunked = ChunkedTree::from_reader(&data[..], 1024, BuildSha256).unwrap();
+        assert!(chunked.len() == 10_000);
+        assert!(chunked.chunk_count() == 10);
+
+        // Chunks hash as their length (u64, big-endian) and bytes
+        let mut tree = HashTree::with_hasher(BuildSha256);
+        tree.insert_hashes(data.chunks(1024).map(|chunk| {
+            let mut hasher = Sha256::new();
+            hasher.update(&(chunk.len() as u64).to_be_bytes());
+            hasher.update(chunk);
+            hasher.finalize()
+        }));
+        assert!(chunked.root_hash() == tree.root_hash());
+        assert!(to_hex(&chunked.root_hash().unwrap()) == "d927a1e9f268eb0279401d91b5f4b8f419975bd6f1cb255648ae049205d4c9f9");
+
+        let verifier = ChunkVerifier::new(chunked.root_hash().unwrap(), 10_000, 1024, BuildSha256).unwrap();
+        for (index, chunk) in data.chunks(1024).enumerate() {
+            let proof = chunked.proof(index).unwrap();
+            assert!(verifier.verify(index, chunk, &proof));
+        }
+        assert!(verifier.chunk_len(9) == Some(10_000 - 9 * 1024));
+
+        let mut corrupted = data[..1024].to_vec();
+        corrupted[5] ^= 1;
+        assert!(!verifier.verify(0, &corrupted, &chunked.proof(0).unwrap()));
+        // Right chunk, wrong position
+        assert!(!verifier.verify(1, &data[..1024], &chunked.proof(0).unwrap()));
+        assert!(!verifier.verify(9, &data[..1024], &chunked.proof(0).unwrap()));
+        assert!(!verifier.verify(10, &data[..1024], &chunked.proof(0).unwrap()));
+    }
+
+    #[test]
+    fn test_chunk_edges() {
+        let empty = ChunkedTree::from_reader(&b""[..], 16, BuildSha256).unwrap();
+        assert!(empty.chunk_count() == 0);
+        assert!(empty.verifier().is_none());
+
+        let data = vec![1u8; 64];
+        let exact = ChunkedTree::from_reader(&data[..], 16, BuildSha256).unwrap();
+        assert!(exact.chunk_count() == 4);
+        let verifier = exact.verifier().unwrap();
+        assert!(verifier.verify(3, &data[48..], &exact.proof(3).unwrap()));
+        assert!(!verifier.verify(3, &data[48..63], &exact.proof(3).unwrap()));
+
+        // Lengths near u64::MAX count their chunks without overflowing
+        //  and refuse counts that don't fit in a usize
+        let root = exact.root_hash().unwrap();
+        match ChunkVerifier::new(root, u64::MAX, 1024, BuildSha256) {
+            Some(huge) => {
+                assert!(huge.chunk_count() as u64 == 1 << 54);
+                assert!(huge.chunk_len(huge.chunk_count() - 1) == Some(1023));
+                assert!(!huge.verify(0, &[0; 1024], &exact.proof(0).unwrap()));
+            },
+            None => assert!(cfg!(target_pointer_width = "32"))
+        }
+    }
+
+    #[test]
+    fn test_dot_export() {
+        let tree = make_tree_of(vec![0, 1, 2]);
+        let dot = to_dot(&tree);
+        assert!(dot.starts_with("digraph merkle {"));
+        assert!(dot.contains("#0\\n") && dot.contains("#1\\n") && dot.contains("#2\\n"));
+        // Three leaves and the full left branch
+        assert!(dot.matches("fillcolor").count() == 4);
+        let lonely = &to_hex(&leaf_hash(2))[..8];
+        assert!(dot.contains(&format!("[label=\"dup\\n{}\", style=dashed]", lonely)));
+        assert!(dot.matches(" -> ").count() == 6);
+
+        let empty: MerkleTree<TestStruct, BuildHasherDefault<DefaultHasher>> = MerkleTree::with_hasher(BuildHasherDefault::default());
+        assert!(!to_dot(&empty).contains(" -> "));
+    }
+
+    #[test]
+    fn test_json_export() {
+        let tree = make_small_tree();
+        let root = tree.root().as_ref().unwrap();
+        let leaf = |hash: [u8; 8]| format!(
+            "{{\"hash\":\"{}\",\"height\":0,\"leaf_count\":1,\"is_final\":true,\"left\":null,\"right\":null}}", to_hex(&hash));
+        let expected = format!(
+            "{{\"hash\":\"{}\",\"height\":1,\"leaf_count\":2,\"is_final\":true,\"left\":{},\"right\":{}}}",
+            to_hex(root.hash_ref()), leaf(leaf_hash(1)), leaf(leaf_hash(2)));
+        assert!(to_json(&tree) == expected);
+
+        let empty: MerkleTree<TestStruct, BuildHasherDefault<DefaultHasher>> = MerkleTree::with_hasher(BuildHasherDefault::default());
+        assert!(to_json(&empty) == "null");
+    }
+
+    #[test]
+    fn test_pretty() {
+        let tree = make_tree_of(vec![0, 1, 2]);
+        let root = tree.root().as_ref().unwrap();
+        let short = |bytes: &[u8]| to_hex(bytes)[..8].to_string();
+        let left = root.left().as_ref().unwrap();
+        let right = root.right().as_ref().unwrap();
+        let expected = vec![
+            format!("{} [3 leaves]", short(root.hash_ref())),
+            format!("├── {} [2 leaves, final]", short(left.hash_ref())),
+            format!("│   ├── #0 {}", short(&leaf_hash(0))),
+            format!("│   └── #1 {}", short(&leaf_hash(1))),
+            format!("└── {} [1 leaf]", short(right.hash_ref())),
+            format!("    ├── #2 {}", short(&leaf_hash(2))),
+            format!("    └── (dup {})", short(&leaf_hash(2))),
+            String::new()
+        ].join("\n");
+        assert!(format!("{:?}", tree) == expected);
+
+        let options = PrettyOptions { hash_length: 4, max_depth: 1, max_width: 1 };
+        let folded = format!("{}", pretty(&tree, options));
+        assert!(folded.lines().count() == 5);
+        assert!(folded.ends_with("└── … 1 leaf\n"));
+        assert!(folded.starts_with(&to_hex(root.hash_ref())[..4]));
+
+        let big = make_tree_of((0..100_000).collect());
+        assert!(format!("{:?}", big).lines().count() < 200);
+    }
+
+    #[test]
+    fn test_hex() {
+        let hash = [0x0a, 0x00, 0xff, 0x10, 0x01, 0xb2, 0x3c, 0x04];
+        assert!(to_hex(&hash) == "0a00ff1001b23c04");
+        assert!(format!("{}", Hex(hash)) == "0a00ff1001b23c04");
+        assert!(format!("{:x}", Hex(hash)) == "0a00ff1001b23c04");
+        assert!("0A00FF1001B23C04".parse::<Hex<[u8; 8]>>() == Ok(Hex(hash)));
+        assert!(from_hex("") == Ok(vec![]));
+        assert!(from_hex("abc") == Err(HexError::OddLength));
+        assert!(from_hex("0g") == Err(HexError::InvalidDigit(1)));
+        assert!(from_hex("é") == Err(HexError::InvalidDigit(0)));
+        assert!("0a00".parse::<Hex<[u8; 8]>>() == Err(HexError::WrongLength(2)));
+
+        let root = make_tree().root_hash().unwrap();
+        assert!(Hex(root).to_string().parse::<Hex<[u8; 8]>>() == Ok(Hex(root)));
+
+        assert!(ct_eq(&hash, &hash));
+        assert!(!ct_eq(&hash, &hash[..7]));
+        assert!(!ct_eq(&hash, &[0x0a, 0x00, 0xff, 0x10, 0x01, 0xb2, 0x3c, 0x05]));
+    }
+
+    #[test]
+    fn test_verify_at() {
+        let tree = make_tree();
+        let root = tree.root_hash().unwrap();
+        let hasher_builder = tree.hasher_builder();
+        for index in 0..7 {
+            let proof = tree.index_proof(index).unwrap();
+            assert!(verify_at(&leaf_hash(index as u64), &proof, index, 7, &root, &hasher_builder) == Ok(()));
+        }
+
+        let proof = tree.index_proof(2).unwrap();
+        assert!(verify_at(&leaf_hash(2), &proof, 7, 7, &root, &hasher_builder)
+            == Err(ProofError::IndexOutOfRange { index: 7, leaf_count: 7 }));
+        assert!(verify_at(&leaf_hash(2), &proof[..2], 2, 7, &root, &hasher_builder)
+            == Err(ProofError::WrongLength { expected: 3, found: 2 }));
+        assert!(verify_at(&leaf_hash(2), &proof, 2, 4, &root, &hasher_builder)
+            == Err(ProofError::WrongLength { expected: 2, found: 3 }));
+        assert!(verify_at(&leaf_hash(2), &proof, 3, 7, &root, &hasher_builder)
+            == Err(ProofError::WrongDirection(0)));
+        assert!(verify_at(&leaf_hash(3), &proof, 2, 7, &root, &hasher_builder)
+            == Err(ProofError::RootMismatch));
+
+        // Leaf 6 has no sibling, its proof must pair it with itself
+        let mut proof = tree.index_proof(6).unwrap();
+        proof[0] = Proof::Right(leaf_hash(5));
+        assert!(verify_at(&leaf_hash(6), &proof, 6, 7, &root, &hasher_builder)
+            == Err(ProofError::BadLonelySibling(0)));
+    }
+
+    #[test]
+    fn test_history() {
+        let tree = make_tree_of((0..13).collect());
+        let hasher_builder = tree.hasher_builder();
+        for size in 1..14 {
+            let old = make_tree_of((0..size as u64).collect());
+            let old_root = old.root_hash().unwrap();
+            assert!(tree.root_hash_at(size) == Some(old_root));
+            for index in 0..size {
+                let proof = tree.inclusion_proof(index, size).unwrap();
+                assert!(proof == old.index_proof(index).unwrap());
+                assert!(verify_at(&leaf_hash(index as u64), &proof, index, size, &old_root, &hasher_builder) == Ok(()));
+            }
+            for new_size in size..14 {
+                let new_root = tree.root_hash_at(new_size).unwrap();
+                let proof = tree.consistency_proof(size, new_size).unwrap();
+                assert!(verify_consistency(size, &old_root, new_size, &new_root, &proof, &hasher_builder) == Ok(()));
+            }
+        }
+        assert!(tree.inclusion_proof(3, 14).is_none());
+        assert!(tree.consistency_proof(0, 5).is_none());
+
+        let root_5 = tree.root_hash_at(5).unwrap();
+        let root_9 = tree.root_hash_at(9).unwrap();
+        let mut proof = tree.consistency_proof(5, 9).unwrap();
+        assert!(verify_consistency(5, &root_5, 9, &root_9, &proof[1..], &hasher_builder).is_err());
+        assert!(verify_consistency(5, &root_9, 9, &root_9, &proof, &hasher_builder) == Err(ProofError::RootMismatch));
+        // A changed old leaf can't be hidden in the appended part
+        let last = proof.len() - 1;
+        proof[last] = leaf_hash(100);
+        assert!(verify_consistency(5, &root_5, 9, &root_9, &proof, &hasher_builder) == Err(ProofError::RootMismatch));
+
+        // Sizes from 2^63 up have subtrees as big as half the address space
+        let huge = 1 << 63;
+        assert!(verify_consistency(huge, &root_5, huge, &root_5, &vec![root_5.clone(); 65], &hasher_builder)
+            == Err(ProofError::WrongLength { expected: 1, found: 65 }));
+        assert!(verify_consistency(huge, &root_5, huge, &root_5, &[root_5.clone()], &hasher_builder) == Ok(()));
+        assert!(verify_consistency(huge, &root_5, usize::MAX, &root_9, &vec![root_5.clone(); 64], &hasher_builder)
+            == Err(ProofError::RootMismatch));
+    }
+
+    #[test]
+    fn test_hmac_sha256() {
+        let hmac = HmacSha256::new(b"Jefe");
+        assert!(to_hex(&hmac.mac(b"what do ya want for nothing?")) == "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+        let long_key = HmacSha256::new(&[0xaa; 131]);
+        assert!(to_hex(&long_key.mac(b"Test Using Larger Than Block-Size Key - Hash Key First"))
+            == "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54");
+        let signature = hmac.sign(b"data");
+        assert!(hmac.verify(b"data", &signature));
+        assert!(!hmac.verify(b"date", &signature));
+        assert!(!HmacSha256::new(b"other").verify(b"data", &signature));
+    }
+
+    #[test]
+    fn test_signed_tree_heads() {
+        let signer = HmacSha256::new(b"log key");
+        let mut tree: MerkleTree<Vec<u8>, BuildSha256> = MerkleTree::with_hasher(BuildSha256);
+        tree.insert_items((0..5u8).map(|i| vec![i]));
+        let old = SignedTreeHead::sign(&tree, 1000, &signer).unwrap();
+        tree.insert_items((5..12u8).map(|i| vec![i]));
+        let new = SignedTreeHead::sign(&tree, 2000, &signer).unwrap();
+        assert!(old.tree_size == 5 && new.tree_size == 12);
+        assert!(old.verify_signature(&signer));
+
+        assert!(SignedTreeHead::decode(&new.encode()) == Ok(new.clone()));
+        let mut next_version = new.encode();
+        next_version[0] = 1;
+        assert!(SignedTreeHead::<[u8; 32]>::decode(&next_version) == Err(DecodeError::UnknownVersion(1)));
+        assert!(SignedTreeHead::<[u8; 32]>::decode(&new.encode()[..40]).is_err());
+
+        let proof = tree.inclusion_proof(3, 5).unwrap();
+        let hash = tree::leaf_hash(&vec![3u8], &BuildSha256);
+        assert!(old.check_inclusion(&signer, &hash, 3, &proof, &BuildSha256) == Ok(()));
+        assert!(new.check_inclusion(&signer, &hash, 3, &tree.index_proof(3).unwrap(), &BuildSha256) == Ok(()));
+
+        let consistency = tree.consistency_proof(5, 12).unwrap();
+        assert!(SignedTreeHead::check_consistency(&old, &new, &signer, &consistency, &BuildSha256) == Ok(()));
+
+        let mut forged = old.clone();
+        forged.timestamp += 1;
+        assert!(forged.check_inclusion(&signer, &hash, 3, &proof, &BuildSha256) == Err(SthError::BadSignature));
+        assert!(SignedTreeHead::check_consistency(&forged, &new, &signer, &consistency, &BuildSha256)
+            == Err(SthError::BadSignature));
+        assert!(old.check_inclusion(&signer, &hash, 4, &proof, &BuildSha256)
+            == Err(SthError::Proof(ProofError::WrongDirection(0))));
+    }
+
+    #[test]
+    fn test_transparency_log() {
+        let signer = HmacSha256::new(b"log key");
+        let mut log = TransparencyLog::new(BuildHasherDefault::<DefaultHasher>::default(), signer.clone());
+        assert!(log.publish(0).is_none());
+        for value in 0..5 {
+            assert!(log.append(TestStruct(value)) == value as usize);
+        }
+        assert!(log.get_entries(0, 10).is_empty());
+        let first = log.publish(100).unwrap().clone();
+        assert!(first.tree_size == 5 && first.verify_signature(&signer));
+
+        for value in 5..12 {
+            log.append(TestStruct(value));
+        }
+        log.append(TestStruct(3));
+        assert!(log.pending_count() == 8);
+        let second = log.publish(200).unwrap().clone();
+        assert!(second.tree_size == 13 && log.heads().len() == 2);
+
+        let entries: Vec<u64> = log.get_entries(3, 6).iter().map(|entry| entry.0).collect();
+        assert!(entries == vec![3, 4, 5]);
+        assert!(log.get_entries(11, 100).len() == 2);
+
+        // Duplicates are proven at their first index
+        let (index, proof) = log.get_proof_by_hash(&leaf_hash(3), 5).unwrap();
+        assert!(index == 3);
+        assert!(first.check_inclusion(&signer, &leaf_hash(3), 3, &proof, &log.tree().hasher_builder()) == Ok(()));
+        assert!(log.get_proof_by_hash(&leaf_hash(7), 5).is_none());
+        assert!(log.get_proof_by_hash(&leaf_hash(100), 13).is_none());
+
+        let consistency = log.get_consistency(5, 13).unwrap();
+        assert!(SignedTreeHead::check_consistency(&first, &second, &signer, &consistency, &log.tree().hasher_builder()) == Ok(()));
+
+        log.append(TestStruct(50));
+        let mut saved = vec![];
+        log.save(&mut saved).unwrap();
+        let loaded: TransparencyLog<TestStruct, _, _> =
+            TransparencyLog::load(&mut &saved[..], BuildHasherDefault::<DefaultHasher>::default(), signer.clone()).unwrap();
+        assert!(loaded.heads() == log.heads());
+        assert!(loaded.tree_size() == 13 && loaded.pending_count() == 1);
+        assert!(loaded.tree().root_hash() == log.tree().root_hash());
+        assert!(loaded.get_proof_by_hash(&leaf_hash(3), 13).unwrap().0 == 3);
+
+        let mut corrupted = saved.clone();
+        corrupted[20] ^= 1;
+        assert!(TransparencyLog::<TestStruct, _, _>::load(&mut &corrupted[..], BuildHasherDefault::<DefaultHasher>::default(), signer.clone()).is_err());
+        // Heads must be signed with the log's key
+        let other_key = HmacSha256::new(b"other key");
+        assert!(TransparencyLog::<TestStruct, _, _>::load(&mut &saved[..], BuildHasherDefault::<DefaultHasher>::default(), other_key).is_err());
+        // and every head, not just the latest, must match the entries
+        let mut other = TransparencyLog::new(BuildHasherDefault::<DefaultHasher>::default(), signer.clone());
+        for value in 100..105 {
+            other.append(TestStruct(value));
+        }
+        let forged = other.publish(100).unwrap().encode();
+        let genuine = first.encode();
+        let at = saved.windows(genuine.len()).position(|window| window == &genuine[..]).unwrap();
+        let mut rewritten = saved.clone();
+        rewritten[at..at + forged.len()].copy_from_slice(&forged);
+        assert!(TransparencyLog::<TestStruct, _, _>::load(&mut &rewritten[..], BuildHasherDefault::<DefaultHasher>::default(), signer).is_err());
+    }
+
+    #[cfg(feature = "server")]
+    fn http(address: ::std::net::SocketAddr, method: &str, target: &str, body: &str) -> (u16, String) {
+        use std::io::{Read, Write};
+
+        let mut stream = ::std::net::TcpStream::connect(address).unwrap();
+        write!(stream, "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}", method, target, body.len(), body).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let status = response[9..12].parse().unwrap();
+        let body = response[response.find("\r\n\r\n").unwrap() + 4..].to_string();
+        (status, body)
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn test_log_server() {
+        use std::net::TcpListener;
+        use std::time::Duration;
+        use server::LogServer;
+
+        let signer = HmacSha256::new(b"server key");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let log = TransparencyLog::new(Rfc6962, signer.clone());
+        thread::spawn(move || LogServer::new(log, Duration::from_secs(0)).serve(listener));
+
+        assert!(http(address, "GET", "/ct/v1/get-sth", "").0 == 400);
+        for i in 0..5u8 {
+            let (status, body) = http(address, "POST", "/ct/v1/add-entry", &format!("{{\"entry\": \"{}\"}}", to_hex(&[i, i])));
+            assert!(status == 200 && body == format!("{{\"leaf_index\":{}}}", i));
+        }
+        let (status, sth) = http(address, "GET", "/ct/v1/get-sth", "");
+        assert!(status == 200 && sth.starts_with("{\"tree_size\":5,"));
+
+        let mut tree = MerkleTree::with_hasher(Rfc6962);
+        tree.insert_items((0..5u8).map(|i| Entry(vec![i, i])));
+        let root = to_hex(&tree.root_hash().unwrap());
+        assert!(sth.contains(&format!("\"root_hash\":\"{}\"", root)));
+
+        // Leaf hashes are RFC 6962's, SHA-256(0x00 || entry)
+        let hash = to_hex(&Sha256::digest(&[0, 3, 3]));
+        let (status, body) = http(address, "GET", &format!("/ct/v1/get-proof-by-hash?hash={}&tree_size=5", hash), "");
+        let path: Vec<String> = tree.index_proof(3).unwrap().iter()
+            .map(|piece| match piece { &Proof::Left(ref hash) | &Proof::Right(ref hash) => format!("\"{}\"", to_hex(hash)) })
+            .collect();
+        assert!(status == 200 && body == format!("{{\"leaf_index\":3,\"audit_path\":[{}]}}", path.join(",")));
+
+        let (status, body) = http(address, "GET", "/ct/v1/get-entries?start=1&end=2", "");
+        assert!(status == 200 && body == "{\"entries\":[{\"leaf_input\":\"0101\"},{\"leaf_input\":\"0202\"}]}");
+
+        http(address, "POST", "/ct/v1/add-entry", "{\"entry\":\"ff\"}");
+        let (status, body) = http(address, "GET", "/ct/v1/get-sth-consistency?first=5&second=6", "");
+        assert!(status == 200 && body.starts_with("{\"consistency\":[\""));
+
+        assert!(http(address, "GET", "/ct/v1/get-entries?start=1", "").0 == 400);
+        let (status, body) = http(address, "GET", &format!("/ct/v1/get-entries?start=0&end={}", usize::MAX), "");
+        assert!(status == 200 && body.matches("leaf_input").count() == 6);
+        assert!(http(address, "POST", "/ct/v1/add-entry", "{\"entry\":\"f\"}").0 == 400);
+        assert!(http(address, "GET", "/nothing", "").0 == 404);
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn test_server_saves_state() {
+        use std::fs::{self, File};
+        use std::time::Duration;
+        use server::{LogServer, EntryLog};
+
+        let path = ::std::env::temp_dir().join(format!("schmerkle-state-{}", ::std::process::id()));
+        let signer = HmacSha256::new(b"server key");
+        let log = TransparencyLog::new(Rfc6962, signer.clone());
+        let mut server = LogServer::new(log, Duration::from_secs(0)).with_state(path.clone());
+        for i in 0..3u8 {
+            server.handle("POST", "/ct/v1/add-entry", format!("{{\"entry\":\"{}\"}}", to_hex(&[i])).as_bytes());
+        }
+        assert!(server.handle("GET", "/ct/v1/get-sth", b"").status == 200);
+
+        let loaded: EntryLog<HmacSha256> =
+            TransparencyLog::load(&mut File::open(&path).unwrap(), Rfc6962, signer).unwrap();
+        assert!(loaded.tree_size() == 3);
+        assert!(!path.with_file_name(format!("schmerkle-state-{}.tmp", ::std::process::id())).exists());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn test_server_request_limits() {
+        use std::io::Cursor;
+        use std::time::Duration;
+        use server::{LogServer, read_request};
+
+        let request = "POST /ct/v1/add-entry HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}";
+        let parsed = read_request(&mut Cursor::new(request.as_bytes())).unwrap();
+        assert!(parsed == ("POST".to_string(), "/ct/v1/add-entry".to_string(), b"{}".to_vec()));
+
+        let long_line = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(10_000));
+        assert!(read_request(&mut Cursor::new(long_line.as_bytes())).is_err());
+        let many_headers = format!("GET / HTTP/1.1\r\n{}\r\n", "X-A: b\r\n".repeat(101));
+        assert!(read_request(&mut Cursor::new(many_headers.as_bytes())).is_err());
+        let enough_headers = format!("GET / HTTP/1.1\r\n{}\r\n", "X-A: b\r\n".repeat(100));
+        assert!(read_request(&mut Cursor::new(enough_headers.as_bytes())).is_ok());
+
+        // get-entries returns at most 1000 entries, from start
+        let mut log = TransparencyLog::new(Rfc6962, HmacSha256::new(b"server key"));
+        for i in 0..1500u32 {
+            log.append(Entry(i.to_be_bytes().to_vec()));
+        }
+        log.publish(0);
+        let mut server = LogServer::new(log, Duration::from_secs(0));
+        let response = server.handle("GET", "/ct/v1/get-entries?start=10&end=1400", b"");
+        assert!(response.status == 200 && response.body.matches("leaf_input").count() == 1000);
+        assert!(response.body.contains("\"0000000a\"") && response.body.contains("\"000003f1\""));
+        assert!(!response.body.contains("\"000003f2\""));
+    }
+
+    #[test]
+    fn test_leaf_positions() {
+        let values = vec![4, 1, 4, 2, 4, 3, 1];
+        let mut indexed = make_tree_of(values[..3].to_vec());
+        indexed.enable_leaf_index();
+        indexed.insert(TestStruct(2));
+        indexed.insert_items(values[4..].iter().cloned().map(TestStruct));
+        let scanned = make_tree_of(values.clone());
+        assert!(indexed.root_hash() == scanned.root_hash());
+
+        for tree in vec![&indexed, &scanned] {
+            assert!(tree.leaf_positions(&leaf_hash(4)) == vec![0, 2, 4]);
+            assert!(tree.leaf_positions(&leaf_hash(1)) == vec![1, 6]);
+            assert!(tree.leaf_positions(&leaf_hash(3)) == vec![5]);
+            assert!(tree.leaf_positions(&leaf_hash(9)).is_empty());
+            assert!(tree.hash_proof(&leaf_hash(3)) == tree.index_proof(5).unwrap());
+        }
+
+        let mut hashes: HashTree<BuildHasherDefault<DefaultHasher>> = HashTree::with_hasher(BuildHasherDefault::default());
+        hashes.enable_leaf_index();
+        hashes.insert_hashes(vec![[1; 8], [2; 8], [1; 8]]);
+        assert!(hashes.leaf_positions(&[1; 8]) == vec![0, 2]);
+    }
+
+    #[test]
+    fn test_duplicate_policy() {
+        let mut allowing = make_tree_of(vec![0, 1, 2]);
+        assert!(allowing.duplicate_policy() == DuplicatePolicy::Allow);
+        allowing.insert(TestStruct(1));
+        assert!(allowing.leaf_positions(&leaf_hash(1)) == vec![1, 3]);
+        let root = allowing.root_hash().unwrap();
+        let hasher_builder = allowing.hasher_builder();
+        for &index in &[1, 3] {
+            let proof = allowing.index_proof(index).unwrap();
+            assert!(verify_at(&leaf_hash(1), &proof, index, 4, &root, &hasher_builder) == Ok(()));
+        }
+
+        let mut rejecting = make_tree_of(vec![0, 1, 2]);
+        rejecting.set_duplicate_policy(DuplicatePolicy::Reject);
+        assert!(rejecting.try_insert(TestStruct(1)) == Err(InsertError::Duplicate(1)));
+        assert!(rejecting.try_insert_items(vec![TestStruct(3), TestStruct(4), TestStruct(3)]) == Err(InsertError::Duplicate(3)));
+        assert!(rejecting.try_insert_items_parallel(vec![TestStruct(5), TestStruct(2)], 2) == Err(InsertError::Duplicate(2)));
+        assert!(rejecting.try_insert_hash(leaf_hash(0)) == Err(InsertError::Duplicate(0)));
+        // Rejected batches leave the tree as it was
+        assert!(rejecting.root_hash() == make_tree_of(vec![0, 1, 2]).root_hash());
+        assert!(rejecting.try_insert_items(vec![TestStruct(3), TestStruct(4)]) == Ok(()));
+        assert!(rejecting.leaf_count() == Some(5));
+
+        let mut deduplicating = make_tree_of(vec![0, 1, 2]);
+        deduplicating.set_duplicate_policy(DuplicatePolicy::Deduplicate);
+        deduplicating.insert(TestStruct(1));
+        deduplicating.insert_items(vec![TestStruct(3), TestStruct(0), TestStruct(3), TestStruct(4)]);
+        assert!(deduplicating.root_hash() == make_tree_of(vec![0, 1, 2, 3, 4]).root_hash());
+        assert!(deduplicating.value_proof(&TestStruct(3)) == deduplicating.index_proof(3).unwrap());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_insert_panics_on_rejected_duplicate() {
+        let mut rejecting = make_tree_of(vec![0, 1, 2]);
+        rejecting.set_duplicate_policy(DuplicatePolicy::Reject);
+        rejecting.insert(TestStruct(1));
+    }
+
+    #[test]
+    fn test_subtree_proof() {
+        let tree = make_tree();
+        let root = tree.root_hash().unwrap();
+        let hasher_builder = tree.hasher_builder();
+        for level in 0..4 {
+            for index in 0..(7 + (1 << level) - 1) >> level {
+                let node = tree.node_at(level, index).unwrap().hash_value();
+                let proof = tree.subtree_proof(level, index).unwrap();
+                assert!(verify_subtree(&node, level, index, &proof, 7, &root, &hasher_builder) == Ok(()));
+            }
+        }
+        assert!(tree.subtree_proof(1, 4).is_none());
+
+        let owned = tree.index_proof(5).unwrap();
+        let borrowed = tree.index_proof_ref(5).unwrap();
+        assert!(borrowed.len() == owned.len());
+        for (borrowed, owned) in borrowed.iter().zip(owned.iter()) {
+            match (borrowed, owned) {
+                (&Proof::Left(first), &Proof::Left(ref second)) => assert!(first == &second[..]),
+                (&Proof::Right(first), &Proof::Right(ref second)) => assert!(first == &second[..]),
+                _ => panic!("sides differ")
+            }
+        }
+        assert!(tree.subtree_proof_ref(1, 4).is_none());
+        assert!(tree.subtree_proof(4, 0).is_none());
+
+        // The first half of the tree is a tree of its own
+        let half = make_tree_of(vec![0, 1, 2, 3]).root_hash().unwrap();
+        let proof = tree.subtree_proof(2, 0).unwrap();
+        assert!(proof.len() == 1);
+        assert!(verify_subtree(&half, 2, 0, &proof, 7, &root, &hasher_builder) == Ok(()));
+        assert!(verify_subtree(&half, 2, 1, &proof, 7, &root, &hasher_builder) == Err(ProofError::WrongDirection(2)));
+        assert!(verify_subtree(&half, 1, 0, &proof, 7, &root, &hasher_builder)
+            == Err(ProofError::WrongLength { expected: 2, found: 1 }));
+        assert!(verify_subtree(&half, 2, 2, &proof, 7, &root, &hasher_builder)
+            == Err(ProofError::IndexOutOfRange { index: 2, leaf_count: 7 }));
+        // An index whose high bits would be shifted out must not alias
+        //  the node at the start of the tree
+        let full = make_tree_of((0..8).collect());
+        let full_root = full.root_hash().unwrap();
+        let pair = full.node_at(1, 0).unwrap().hash_value();
+        let proof = full.subtree_proof(1, 0).unwrap();
+        assert!(verify_subtree(&pair, 1, 0, &proof, 8, &full_root, &hasher_builder) == Ok(()));
+        assert!(verify_subtree(&pair, 1, 1 << 63, &proof, 8, &full_root, &hasher_builder)
+            == Err(ProofError::IndexOutOfRange { index: 1 << 63, leaf_count: 8 }));
+        // Trees that are subtrees are found wherever they sit,
+        //  including partial ones on the right edge
+        let tail = make_tree_of(vec![4, 5, 6]);
+        let (index, proof) = tree.tree_proof(&tail).unwrap();
+        assert!(index == 1);
+        assert!(verify_subtree(&tail.root_hash().unwrap(), 2, index, &proof, 7, &root, &hasher_builder) == Ok(()));
+        assert!(tree.tree_proof(&make_tree_of(vec![4, 5])).unwrap().0 == 2);
+        assert!(tree.tree_proof(&make_tree_of(vec![1, 2])).is_none());
+        assert!(tree.tree_proof(&make_tree_of(vec![])).is_none());
+
+        // The root is proven by an empty path, but only at the top
+        assert!(verify_subtree(&root, 3, 0, &[], 7, &root, &hasher_builder) == Ok(()));
+        assert!(verify_subtree(&root, 0, 0, &[], 7, &root, &hasher_builder).is_err());
+        let other = make_tree_of(vec![9, 9]).root_hash().unwrap();
+        assert!(verify_subtree(&other, 1, 0, &tree.subtree_proof(1, 0).unwrap(), 7, &root, &hasher_builder)
+            == Err(ProofError::RootMismatch));
+    }
+
+    #[test]
+    fn test_bitcoin_merkle_root() {
+        // Block 100000
+        let txids: Vec<_> = vec![
+            "8c14f0db3df150123e6f3dbbf30f8b955a8249b62ac1d1ff16284aefa3d06d87",
+            "fff2525b8931402dd09222c50775608f75787bd2b87e56995a7bdd30f79702c4",
+            "6359f0868171b1d194cbee1af2f16ea598ae8fad666d9b012c8ed2b79a236ec4",
+            "e9a66845e05d5abc0ad04ec80f774a7e585c6e8db975962d069a522137b80c1d"
+        ].into_iter().map(|txid| bitcoin::parse_hash(txid).unwrap()).collect();
+        let root = bitcoin::merkle_root(&txids).unwrap();
+        assert!(bitcoin::display_hash(&root) == "f3e94742aca4b5ef85488dc37c06c3282295ffec960994b2c0d5ac2a25a95766");
+        // A lone coinbase is its own root, odd levels repeat their last hash
+        assert!(bitcoin::merkle_root(&txids[..1]) == Some(txids[0]));
+        let pair = bitcoin::double_sha256(&[&txids[2][..], &txids[2][..]].concat());
+        let left = bitcoin::double_sha256(&[&txids[0][..], &txids[1][..]].concat());
+        assert!(bitcoin::merkle_root(&txids[..3]) == Some(bitcoin::double_sha256(&[&left[..], &pair[..]].concat())));
+        assert!(bitcoin::merkle_root(&[]).is_none());
+
+        let tree = bitcoin::tx_tree(&txids);
+        for (index, txid) in txids.iter().enumerate() {
+            let mut matches = vec![false; 4];
+            matches[index] = true;
+            let partial = PartialMerkleTree::from_tree(&tree, &matches).unwrap();
+            assert!(partial.extract_matches() == Ok((root, vec![(index, *txid)])));
+            assert!(PartialMerkleTree::decode(&partial.encode()).unwrap().extract_matches() == Ok((root, vec![(index, *txid)])));
+        }
+        let all = PartialMerkleTree::from_tree(&tree, &[true; 4]).unwrap();
+        assert!(all.hashes == txids);
+        let none = PartialMerkleTree::from_tree(&tree, &[false; 4]).unwrap();
+        assert!(none.hashes == vec![root] && none.extract_matches() == Ok((root, vec![])));
+        assert!(PartialMerkleTree::from_tree(&tree, &[true; 3]).is_none());
+        let too_many = bitcoin::MAX_TRANSACTIONS as usize + 1;
+        let huge = bitcoin::tx_tree(&vec![[0u8; 32]; too_many]);
+        assert!(PartialMerkleTree::from_tree(&huge, &vec![false; too_many]).is_none());
+    }
+
+    #[test]
+    fn test_bitcoin_merkle_block() {
+        // merkleblock message from the Bitcoin developer reference
+        let bytes = from_hex(&[
+            "01000000",
+            "82bb869cf3a793432a66e826e05a6fc37469f8efb7421dc88067010000000000",
+            "7f16c5962e8bd963659c793ce370d95f093bc7e367117b3c30c1f8fdd0d97287",
+            "76381b4d4c86041b554b8529",
+            "07000000",
+            "04",
+            "3612262624047ee87660be1a707519a443b1c1ce3d248cbfc6c15870f6c5daa2",
+            "019f5b01d4195ecbc9398fbf3c3b1fa9bb3183301d7a1fb3bd174fcfa40a2b65",
+            "41ed70551dd7e841883ab8f0b16bf04176b7d1480e4f0af9f3d4c3595768d068",
+            "20d2a7bc994987302e5b1ac80fc425fe25f8b63169ea78e68fbaaefa59379bbf",
+            "011d"
+        ].concat()).unwrap();
+        let block = MerkleBlock::decode(&bytes).unwrap();
+        assert!(block.tree.tx_count == 7 && block.tree.hashes.len() == 4 && block.tree.flags.len() == 8);
+        assert!(block.encode() == bytes);
+        assert!(bitcoin::display_hash(&block.block_hash()) == "000000000000b731f2eef9e8c63173adfb07e41bd53eb0ef0a6b720d6cb6dea4");
+        let txid = bitcoin::parse_hash("652b0aa4cf4f17bdb31f7a1d308331bba91f3b3cbf8f39c9cb5e19d4015b9f01").unwrap();
+        assert!(block.verify() == Ok(vec![(4, txid)]));
+
+        let mut wrong = block.clone();
+        wrong.header[40] ^= 1;
+        assert!(wrong.verify() == Err(PartialTreeError::RootMismatch));
+        let mut wrong = block.clone();
+        wrong.tree.hashes.pop();
+        assert!(wrong.verify() == Err(PartialTreeError::NotEnoughHashes));
+        let mut wrong = block.clone();
+        wrong.tree.flags.extend(vec![false; 8]);
+        assert!(wrong.verify() == Err(PartialTreeError::UnusedFlags));
+        let mut wrong = block.clone();
+        wrong.tree.tx_count = 0;
+        assert!(wrong.verify() == Err(PartialTreeError::NoTransactions));
+        assert!(MerkleBlock::decode(&bytes[..bytes.len() - 1]) == Err(DecodeError::UnexpectedEnd));
+        assert!(MerkleBlock::decode(&[&bytes[..], &[0][..]].concat()) == Err(DecodeError::TrailingBytes));
+        // 4 hashes as a 3 byte compact size
+        let mut padded = bytes[..84].to_vec();
+        padded.extend_from_slice(&[0xfd, 4, 0]);
+        padded.extend_from_slice(&bytes[85..]);
+        assert!(MerkleBlock::decode(&padded) == Err(DecodeError::NonCanonical));
+
+        // Mutated blocks: repeating the last txids keeps the root
+        let txids: Vec<_> = (0..6u8).map(|i| bitcoin::double_sha256(&[i])).collect();
+        let mut mutated = txids.clone();
+        mutated.extend_from_slice(&txids[4..]);
+        assert!(bitcoin::merkle_root(&txids) == bitcoin::merkle_root(&mutated));
+        let mut matches = vec![false; 8];
+        matches[7] = true;
+        let partial = PartialMerkleTree::from_tree(&bitcoin::tx_tree(&mutated), &matches).unwrap();
+        assert!(partial.extract_matches() == Err(PartialTreeError::DuplicateSibling));
+    }
+
+    #[test]
+    fn test_sorted_pairs() {
+        let hasher_builder = SortedPairs(BuildSha256);
+        let leaves: Vec<_> = (0..7u8)
+            .map(|i| openzeppelin::standard_leaf_hash(&[i], &hasher_builder))
+            .collect();
+        assert!(leaves[0] == Sha256::digest(&Sha256::digest(&[0])));
+        let mut tree = HashTree::with_hasher(hasher_builder.clone());
+        tree.insert_hashes(leaves.clone());
+        let root = tree.root_hash().unwrap();
+
+        // Pairs are hashed smallest first, without length prefixes
+        let pair = |a: &[u8; 32], b: &[u8; 32]| {
+            if a <= b { Sha256::digest(&[&a[..], &b[..]].concat()) } else { Sha256::digest(&[&b[..], &a[..]].concat()) }
+        };
+        let left = pair(&pair(&leaves[0], &leaves[1]), &pair(&leaves[2], &leaves[3]));
+        let lonely = pair(&leaves[6], &leaves[6]);
+        let right = pair(&pair(&leaves[4], &leaves[5]), &lonely);
+        assert!(root == pair(&left, &right));
+
+        for (index, leaf) in leaves.iter().enumerate() {
+            let proof = openzeppelin::proof(&tree, index).unwrap();
+            assert!(openzeppelin::verify(leaf, &proof, &root, &hasher_builder));
+            assert!(!openzeppelin::verify(&leaves[(index + 1) % 7], &proof, &root, &hasher_builder));
+            // Directions don't matter, so the usual proofs work too
+            assert!(verify(leaf, &tree.index_proof(index).unwrap(), &root, &hasher_builder));
+        }
+        assert!(openzeppelin::proof(&tree, 6).unwrap() == vec![leaves[6], pair(&leaves[4], &leaves[5]), left]);
+        assert!(openzeppelin::proof(&tree, 7).is_none());
+
+        let subsets: Vec<Vec<usize>> = vec![
+            vec![], vec![0], vec![6], vec![0, 1], vec![1, 2], vec![5, 6], vec![0, 3, 6], vec![6, 2, 2], (0..7).collect()
+        ];
+        for indices in subsets {
+            let multi_proof = openzeppelin::multi_proof(&tree, &indices).unwrap();
+            assert!(openzeppelin::verify_multi_proof(&multi_proof, &root, &hasher_builder));
+            let mut wrong = multi_proof.clone();
+            wrong.proof_flags.push(true);
+            assert!(!openzeppelin::verify_multi_proof(&wrong, &root, &hasher_builder));
+        }
+        let multi_proof = openzeppelin::multi_proof(&tree, &[4, 5]).unwrap();
+        assert!(multi_proof == MultiProof {
+            leaves: vec![leaves[4], leaves[5]],
+            proof: vec![lonely, left],
+            proof_flags: vec![true, false, false]
+        });
+        let mut wrong = multi_proof.clone();
+        wrong.leaves.swap(0, 1);
+        assert!(openzeppelin::verify_multi_proof(&wrong, &root, &hasher_builder));
+        wrong.leaves[0] = leaves[3];
+        assert!(!openzeppelin::verify_multi_proof(&wrong, &root, &hasher_builder));
+        assert!(openzeppelin::multi_proof(&tree, &[7]).is_none());
+
+        // A single leaf is its own root
+        let mut single = HashTree::with_hasher(hasher_builder.clone());
+        single.insert_hash(leaves[0]);
+        let multi_proof = openzeppelin::multi_proof(&single, &[0]).unwrap();
+        assert!(multi_proof.proof.is_empty() && multi_proof.proof_flags.is_empty());
+        assert!(openzeppelin::verify_multi_proof(&multi_proof, &leaves[0], &hasher_builder));
+    }
+
+    #[test]
+    fn test_keccak256() {
+        assert!(to_hex(&Keccak256::digest(b"")) == "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470");
+        assert!(to_hex(&Keccak256::digest(b"abc")) == "4e03657aea45a94fc7d47ba826c8d667c0d1e6e33a64a036ec44f58fa12d6c45");
+        // Root of the empty trie, keccak256(rlp(""))
+        assert!(to_hex(&Keccak256::digest(&[0x80])) == "56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421");
+        assert!(to_hex(&Keccak256::digest(b"transfer(address,uint256)")[..4]) == "a9059cbb");
+        let long = [b'a'; 200];
+        assert!(to_hex(&Keccak256::digest(&long)) == "96ea54061def936c4be90b518992fdc6f12f535068a256229aca54267b4d084d");
+        // Fed in pieces across the 136 byte blocks
+        let mut hasher = Keccak256::new();
+        for piece in long.chunks(7) {
+            hasher.update(piece);
+        }
+        assert!(hasher.finalize() == Keccak256::digest(&long));
+
+        // Branches hash the raw pair, like abi.encodePacked
+        assert!(BuildKeccak256.hash_branch(b"ab", b"c") == Keccak256::digest(b"abc"));
+
+        // Leaves from raw bytes, as merkletreejs and Solidity take them:
+        //  keccak256("a"), not the hash of a length-prefixed Vec<u8>
+        let leaves: Vec<[u8; 32]> = [&b"a"[..], b"b", b"c"].iter().map(|leaf| Keccak256::digest(leaf)).collect();
+        assert!(to_hex(&leaves[0]) == "3ac225168df54212a25c1c01fd35bebfea408fdac2e31ddd6f80a4bbf9a5f1cb");
+        assert!(tree::leaf_hash(&b"a".to_vec(), &BuildKeccak256) != leaves[0]);
+        let mut tree = HashTree::with_hasher(BuildKeccak256);
+        tree.insert_hashes(leaves);
+        assert!(to_hex(&tree.root_hash().unwrap()) == "905b17edcf8b6fb1415b32cdbab3e02c2c93f80a345de80ea2bbf9feba9f5a55");
+
+        // Sorted-pair root of StandardMerkleTree-style leaves for abi.encode(uint256(i))
+        let hasher_builder = SortedPairs(BuildKeccak256);
+        let mut tree = HashTree::with_hasher(hasher_builder.clone());
+        tree.insert_hashes((0..4u8).map(|i| {
+            let mut encoded = [0u8; 32];
+            encoded[31] = i;
+            openzeppelin::standard_leaf_hash(&encoded, &hasher_builder)
+        }));
+        assert!(to_hex(&tree.root_hash().unwrap()) == "e97e7870ffec941070df3ac0255521c04008ba2c5a2b9ebb33e0d4adee758162");
+    }
+
+    #[test]
+    fn test_rfc6962() {
+        // Certificate Transparency's test vectors, which match ours
+        //  for sizes that are powers of two
+        let entries: Vec<Entry> = vec![vec![], vec![0x00], vec![0x10], vec![0x20, 0x21]].into_iter().map(Entry).collect();
+        assert!(to_hex(&tree::leaf_hash(&entries[0], &Rfc6962)) == "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d");
+        assert!(tree::leaf_hash(&entries[3], &Rfc6962) == Sha256::digest(&[0x00, 0x20, 0x21]));
+        let mut tree = MerkleTree::with_hasher(Rfc6962);
+        tree.insert_items(entries[..2].to_vec());
+        assert!(to_hex(&tree.root_hash().unwrap()) == "fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125");
+        tree.insert_items(entries[2..].to_vec());
+        assert!(to_hex(&tree.root_hash().unwrap()) == "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7");
+        assert!(Entry::from_bytes(&[1, 2]) == Some(Entry(vec![1, 2])));
+    }
+
+    #[test]
+    fn test_rlp() {
+        assert!(to_hex(&rlp::encode_bytes(b"dog")) == "83646f67");
+        assert!(to_hex(&rlp::encode_bytes(b"")) == "80");
+        assert!(to_hex(&rlp::encode_bytes(&[0x0f])) == "0f");
+        assert!(to_hex(&rlp::encode_bytes(&[0x80])) == "8180");
+        assert!(to_hex(&rlp::encode_bytes(&[0x04, 0x00])) == "820400");
+        let cat_dog = rlp::encode_list(&[rlp::encode_bytes(b"cat"), rlp::encode_bytes(b"dog")]);
+        assert!(to_hex(&cat_dog) == "c88363617483646f67");
+        assert!(to_hex(&rlp::encode_list(&[])) == "c0");
+        let lorem = b"Lorem ipsum dolor sit amet, consectetur adipisicing elit";
+        assert!(rlp::encode_bytes(lorem) == [&[0xb8, 0x38][..], &lorem[..]].concat());
+
+        // [ [], [[]], [ [], [[]] ] ]
+        let empty = Rlp::List(vec![]);
+        let nested = Rlp::List(vec![empty.clone()]);
+        let set = Rlp::List(vec![empty.clone(), nested.clone(), Rlp::List(vec![empty.clone(), nested.clone()])]);
+        let encoded = rlp::encode(&set);
+        assert!(to_hex(&encoded) == "c7c0c1c0c3c0c1c0");
+        assert!(rlp::decode(&encoded) == Ok(set));
+        assert!(rlp::decode(&cat_dog) == Ok(Rlp::List(vec![Rlp::Bytes(b"cat"), Rlp::Bytes(b"dog")])));
+        let long = vec![7u8; 1000];
+        assert!(rlp::decode(&rlp::encode_bytes(&long)) == Ok(Rlp::Bytes(&long)));
+
+        assert!(rlp::decode(&[0x81, 0x05]) == Err(DecodeError::NonCanonical));
+        assert!(rlp::decode(&[&[0xb8, 0x03][..], b"dog"].concat()) == Err(DecodeError::NonCanonical));
+        assert!(rlp::decode(&[0xb9, 0x00, 0x40]) == Err(DecodeError::NonCanonical));
+        assert!(rlp::decode(&[0x83, b'd', b'o']) == Err(DecodeError::UnexpectedEnd));
+        assert!(rlp::decode(&[0xc2, 0x83, b'd']) == Err(DecodeError::UnexpectedEnd));
+        assert!(rlp::decode(&[0x80, 0x80]) == Err(DecodeError::TrailingBytes));
+    }
+
+    #[test]
+    fn test_patricia_trie() {
+        fn trie_of(pairs: &[(&str, &str)]) -> PatriciaTrie<BuildKeccak256> {
+            let mut trie = PatriciaTrie::new(BuildKeccak256);
+            for &(key, value) in pairs {
+                trie.insert(key.as_bytes(), value.as_bytes().to_vec());
+            }
+            trie
+        }
+
+        // From Ethereum's trie tests
+        let empty = trie_of(&[]);
+        assert!(to_hex(&empty.root_hash()) == "56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421");
+        let vectors: Vec<(Vec<(&str, &str)>, &str)> = vec![
+            (vec![("do", "verb"), ("horse", "stallion"), ("doge", "coin"), ("dog", "puppy")],
+                "5991bb8c6514148a29db676a14ac506cd2cd5775ace63c30a4fe457715e9ac84"),
+            (vec![("doe", "reindeer"), ("dog", "puppy"), ("dogglesworth", "cat")],
+                "8aad789dff2f538bca5d8ea56e8abe10f4c7ba3a5dea95fea4cd6e7c3a1168d3"),
+            (vec![("foo", "bar"), ("food", "bass")],
+                "17beaa1648bafa633cda809c90c04af50fc8aed3cb40d16efbddee6fdf63c4c3"),
+            (vec![("be", "e"), ("dog", "puppy"), ("bed", "d")],
+                "3f67c7a47520f79faa29255d2d3c084a7a6df0453116ed7232ff10277a8be68b"),
+            (vec![("test", "test"), ("te", "testy")],
+                "8452568af70d8d140f58d941338542f645fcca50094b20f3c3d8c3df49337928"),
+            (vec![("A", "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa")],
+                "d23786fb4a010da3ce639d66d5e904a11dbc02746d1ce25029e53290cabf28ab")
+        ];
+        for (pairs, root) in vectors {
+            let trie = trie_of(&pairs);
+            assert!(to_hex(&trie.root_hash()) == root);
+            assert!(trie.len() == pairs.len());
+            let reversed: Vec<_> = pairs.iter().rev().cloned().collect();
+            assert!(to_hex(&trie_of(&reversed).root_hash()) == root);
+            for &(key, value) in &pairs {
+                assert!(trie.get(key.as_bytes()) == Some(value.as_bytes()));
+            }
+        }
+
+        // Deleting gives the trie that never had the keys
+        let mut trie = trie_of(&[("do", "verb"), ("horse", "stallion"), ("doge", "coin"), ("dog", "puppy")]);
+        assert!(trie.get(b"d").is_none() && trie.get(b"dogs").is_none());
+        assert!(trie.delete(b"dog") == Some(b"puppy".to_vec()));
+        assert!(trie.delete(b"dog").is_none());
+        assert!(trie.root_hash() == trie_of(&[("do", "verb"), ("horse", "stallion"), ("doge", "coin")]).root_hash());
+        assert!(trie.insert(b"doge", vec![]) == Some(b"coin".to_vec()));
+        assert!(trie.insert(b"do", b"jump".to_vec()) == Some(b"verb".to_vec()));
+        assert!(trie.root_hash() == trie_of(&[("horse", "stallion"), ("do", "jump")]).root_hash());
+        trie.delete(b"do");
+        trie.delete(b"horse");
+        assert!(trie.is_empty() && trie.root_hash() == empty.root_hash());
+
+        // Proofs of values and of their absence
+        let trie = trie_of(&[("do", "verb"), ("horse", "stallion"), ("doge", "coin"), ("dog", "puppy"), ("dogglesworth", "cat")]);
+        let root = trie.root_hash();
+        for key in &["do", "horse", "doge", "dog", "dogglesworth", "d", "dogs", "horses", "zebra", ""] {
+            let proof = trie.proof(key.as_bytes());
+            let value = trie.get(key.as_bytes()).map(|value| value.to_vec());
+            assert!(patricia::verify_proof(&root, key.as_bytes(), &proof, &BuildKeccak256) == Ok(value));
+        }
+        let proof = trie.proof(b"dogglesworth");
+        assert!(patricia::verify_proof(&root, b"dogglesworth", &proof[..proof.len() - 1], &BuildKeccak256)
+            == Err(TrieError::MissingNode(proof.len() - 1)));
+        let mut extended = proof.clone();
+        extended.push(proof[0].clone());
+        assert!(patricia::verify_proof(&root, b"dogglesworth", &extended, &BuildKeccak256) == Err(TrieError::UnusedNodes));
+        let mut tampered = proof.clone();
+        let last = tampered.len() - 1;
+        *tampered[last].last_mut().unwrap() ^= 1;
+        assert!(patricia::verify_proof(&root, b"dogglesworth", &tampered, &BuildKeccak256) == Err(TrieError::HashMismatch(last)));
+        assert!(patricia::verify_proof(&empty.root_hash(), b"dog", &proof, &BuildKeccak256) == Err(TrieError::HashMismatch(0)));
+        assert!(empty.proof(b"dog").is_empty());
+        assert!(patricia::verify_proof(&empty.root_hash(), b"dog", &[], &BuildKeccak256) == Ok(None));
+        assert!(patricia::verify_proof(&root, b"dog", &[], &BuildKeccak256) == Err(TrieError::MissingNode(0)));
+        assert!(patricia::verify_proof(&root, b"dog", &[rlp::encode_bytes(b"dog")], &BuildKeccak256) == Err(TrieError::HashMismatch(0)));
+    }
+}
+
+#[cfg(all(test, feature = "bench"))]
+mod benches {
+    extern crate test;
+
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::BuildHasherDefault;
+    use self::test::Bencher;
+
+    use tree::MerkleTree;
+
+    type BenchTree = MerkleTree<u64, BuildHasherDefault<DefaultHasher>>;
+
+    fn make_tree(count: u64) -> BenchTree {
+        let mut tree = MerkleTree::with_hasher(BuildHasherDefault::default());
+        tree.insert_items(0..count);
+        tree
+    }
+
+    fn insert_one_by_one(count: u64) -> BenchTree {
+        let mut tree = MerkleTree::with_hasher(BuildHasherDefault::default());
+        for value in 0..count {
+            tree.insert(value);
+        }
+        tree
+    }
+
+    #[bench]
+    fn bench_insert_one_by_one_1k(b: &mut Bencher) {
+        b.iter(|| insert_one_by_one(1_000));
+    }
+
+    #[bench]
+    fn bench_insert_one_by_one_4k(b: &mut Bencher) {
+        b.iter(|| insert_one_by_one(4_000));
+    }
+
+    #[bench]
+    fn bench_insert_items_4k(b: &mut Bencher) {
+        b.iter(|| make_tree(4_000));
+    }
+
+    #[bench]
+    fn bench_append_to_100k(b: &mut Bencher) {
+        let mut tree = make_tree(100_000);
+        let mut next = 100_000;
+        b.iter(|| {
+            tree.insert(next);
+            next += 1;
+        });
+    }
+
+    #[bench]
+    fn bench_index_proof_100k(b: &mut Bencher) {
+        let tree = make_tree(100_000);
+        let mut index = 0;
+        b.iter(|| {
+            index = (index + 7919) % 100_000;
+            tree.index_proof(index)
+        });
+    }
+}
@@ -0,0 +1,219 @@
+use std::cmp::min;
+use std::convert::TryFrom;
+use std::hash::Hash;
+use std::io::{self, Read, Write};
+
+use hash::BuildMerkleHasher;
+use node::HashValue;
+use tree::{MerkleTree, Proof};
+use sth::{SignedTreeHead, Signer, Verifier};
+use sync::Record;
+use codec::{DecodeError, Reader, put_u64, put_bytes};
+use hex::ct_eq;
+
+
+// Append-only transparency log on top of MerkleTree.
+//
+// Entries are queued by append(), which hands out their index right
+//  away, and become part of the tree at the next publish(). Every
+//  publish() signs a new tree head, the caller decides how often.
+//  Everything a client can ask for, entries and proofs, only covers
+//  published heads, so answers always match some head it can get.
+//
+// Requests follow the usual log API:
+//  get_entries(start, end)           entries of the latest head
+//  get_proof_by_hash(hash, size)     index and inclusion proof
+//  get_consistency(first, second)    consistency proof between sizes
+//
+// save() writes entries, pending entries and heads, load() rebuilds
+//  the tree and checks every head's signature and root against it.
+//  Entries are stored as Record bytes, heads with
+//  SignedTreeHead::encode():
+//
+//   magic "SCHLOG", version (1 byte, 0)
+//   entry count (u64), then each entry as length-prefixed bytes
+//   pending count (u64), then each entry the same way
+//   head count (u64), then each encoded head as length-prefixed bytes
+
+const MAGIC: &'static [u8] = b"SCHLOG";
+const VERSION: u8 = 0;
+
+pub struct TransparencyLog<V, S, G>
+where
+    V: Hash + Clone + Record,
+    S: BuildMerkleHasher,
+    G: Signer
+{
+    tree: MerkleTree<V, S>,
+    signer: G,
+    pending: Vec<V>,
+    heads: Vec<SignedTreeHead<HashValue<S>>>
+}
+
+impl<V, S, G> TransparencyLog<V, S, G>
+where
+    V: Hash + Clone + Record,
+    S: BuildMerkleHasher,
+    G: Signer
+{
+    pub fn new(hasher_builder: S, signer: G) -> TransparencyLog<V, S, G> {
+        let mut tree = MerkleTree::with_hasher(hasher_builder);
+        tree.enable_leaf_index();
+        TransparencyLog {
+            tree: tree,
+            signer: signer,
+            pending: vec![],
+            heads: vec![]
+        }
+    }
+
+    // Queues an entry for the next head, returns the index it'll have
+    pub fn append(&mut self, value: V) -> usize {
+        self.pending.push(value);
+        self.tree_size() + self.pending.len() - 1
+    }
+
+    // Adds pending entries and signs a head over all of them.
+    // None while the log is empty.
+    pub fn publish(&mut self, timestamp: u64) -> Option<&SignedTreeHead<HashValue<S>>> {
+        self.integrate_pending();
+        let head = SignedTreeHead::sign(&self.tree, timestamp, &self.signer)?;
+        self.heads.push(head);
+        self.heads.last()
+    }
+
+    pub fn latest_head(&self) -> Option<&SignedTreeHead<HashValue<S>>> {
+        self.heads.last()
+    }
+
+    // Every head published so far, oldest first
+    pub fn heads(&self) -> &[SignedTreeHead<HashValue<S>>] {
+        &self.heads
+    }
+
+    // Size of the latest head
+    pub fn tree_size(&self) -> usize {
+        self.tree.leaf_count().unwrap_or(0)
+    }
+
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    // Published entries in [start, end), cut at the latest head's size
+    pub fn get_entries(&self, start: usize, end: usize) -> Vec<&V> {
+        let end = min(end, self.tree_size());
+        (start..end)
+            .filter_map(|index| self.tree.leaf(index))
+            .collect()
+    }
+
+    // Index of the first entry with the given leaf hash and its proof
+    //  in the tree of `tree_size` entries
+    pub fn get_proof_by_hash(&self, hash: &HashValue<S>, tree_size: usize) -> Option<(usize, Vec<Proof<HashValue<S>>>)> {
+        let index = *self.tree.leaf_positions(hash).first()?;
+        let proof = self.tree.inclusion_proof(index, tree_size)?;
+        Some((index, proof))
+    }
+
+    pub fn get_consistency(&self, first: usize, second: usize) -> Option<Vec<HashValue<S>>> {
+        self.tree.consistency_proof(first, second)
+    }
+
+    pub fn tree(&self) -> &MerkleTree<V, S> {
+        &self.tree
+    }
+
+    pub fn save<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        put_u64(&mut bytes, self.tree_size() as u64);
+        for index in 0..self.tree_size() {
+            let value = self.tree.leaf(index)
+                .ok_or(io::Error::new(io::ErrorKind::Other, "log entries must be values"))?;
+            put_bytes(&mut bytes, &value.to_bytes());
+        }
+        put_u64(&mut bytes, self.pending.len() as u64);
+        for value in &self.pending {
+            put_bytes(&mut bytes, &value.to_bytes());
+        }
+        put_u64(&mut bytes, self.heads.len() as u64);
+        for head in &self.heads {
+            put_bytes(&mut bytes, &head.encode());
+        }
+        out.write_all(&bytes)
+    }
+
+    // Reads a log written by save(). Every head must carry the signer's
+    //  signature and the root the rebuilt tree had at its size, and the
+    //  latest one must cover all the entries.
+    pub fn load<R: Read>(input: &mut R, hasher_builder: S, signer: G) -> io::Result<TransparencyLog<V, S, G>>
+    where
+        G: Verifier
+    {
+        let mut bytes = vec![];
+        input.read_to_end(&mut bytes)?;
+        let mut reader = Reader { bytes: &bytes };
+        if reader.take(MAGIC.len()).map_err(invalid)? != MAGIC || reader.byte().map_err(invalid)? != VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a transparency log"))
+        }
+
+        let mut log = TransparencyLog::new(hasher_builder, signer);
+        log.pending = read_entries(&mut reader)?;
+        log.integrate_pending();
+        log.pending = read_entries(&mut reader)?;
+        for _ in 0..reader.u64().map_err(invalid)? {
+            let head = SignedTreeHead::decode(&reader.bytes().map_err(invalid)?).map_err(invalid)?;
+            log.heads.push(head);
+        }
+        if !reader.bytes.is_empty() {
+            return Err(invalid(DecodeError::TrailingBytes))
+        }
+
+        // Heads never shrink, each is signed and has the root of
+        //  the entries it covers
+        let mut previous_size = 0;
+        for head in &log.heads {
+            if !head.verify_signature(&log.signer) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "tree head with a bad signature"))
+            }
+            let size = usize::try_from(head.tree_size).ok().filter(|&size| size >= previous_size);
+            let matches = size.and_then(|size| log.tree.root_hash_at(size))
+                .map_or(false, |root| ct_eq(root.as_ref(), head.root_hash.as_ref()));
+            if !matches {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "entries don't match a tree head"))
+            }
+            previous_size = head.tree_size as usize;
+        }
+        let matches = match log.heads.last() {
+            Some(head) => head.tree_size as usize == log.tree_size()
+                && log.tree.root_hash().map_or(false, |root| ct_eq(root.as_ref(), head.root_hash.as_ref())),
+            None => log.tree_size() == 0
+        };
+        if !matches {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "entries don't match the latest head"))
+        }
+        Ok(log)
+    }
+
+    fn integrate_pending(&mut self) {
+        let pending: Vec<V> = self.pending.drain(..).collect();
+        self.tree.insert_items(pending);
+    }
+}
+
+fn read_entries<V: Record>(reader: &mut Reader) -> io::Result<Vec<V>> {
+    let count = reader.u64().map_err(invalid)?;
+    let mut entries = vec![];
+    for _ in 0..count {
+        let bytes = reader.bytes().map_err(invalid)?;
+        let value = V::from_bytes(&bytes)
+            .ok_or(io::Error::new(io::ErrorKind::InvalidData, "invalid log entry"))?;
+        entries.push(value);
+    }
+    Ok(entries)
+}
+
+fn invalid(err: DecodeError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", err))
+}
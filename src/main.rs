@@ -0,0 +1,247 @@
+extern crate schmerkle;
+
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::process;
+
+use schmerkle::sha256::{Sha256, BuildSha256};
+use schmerkle::tree::{HashTree, Proof, verify};
+use schmerkle::manifest::{self, Manifest, Change};
+use schmerkle::hex::{Hex, to_hex, ct_eq};
+
+
+// Command-line front end: SHA-256 trees over records.
+//
+// Records are lines of a file (stdin when no file is given) without
+//  the trailing newline, or whole files when --files is passed.
+// A record's leaf is the SHA-256 of its length as a big-endian u64
+//  followed by its bytes, a branch the SHA-256 of its children's
+//  hashes, so hashes don't depend on the platform.
+// Proofs are printed one sibling per line, from leaf to root,
+//  as `left <hex>` or `right <hex>`.
+//
+// Directory manifests start with `root <hex>`, followed by
+//  `<hex>  <path>` per file (see manifest.rs for how files are hashed).
+
+const USAGE: &'static str = "usage:
+    schmerkle root [FILE]                     root hash of the lines of FILE or stdin
+    schmerkle root --files FILE...            root hash over whole files
+    schmerkle prove N [FILE]                  inclusion proof for line N (from 0)
+    schmerkle prove N --files FILE...         inclusion proof for the N-th file
+    schmerkle verify ROOT RECORD [PROOF]      check a proof read from PROOF or stdin
+    schmerkle verify ROOT --file PATH [PROOF] same, with the record taken from a file
+    schmerkle dir-hash DIR                    manifest of DIR with per-file hashes and root
+    schmerkle dir-verify DIR MANIFEST         report files of DIR that differ from MANIFEST";
+
+type Tree = HashTree<BuildSha256>;
+type Hash = [u8; 32];
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.first().map(|arg| arg.as_str()) {
+        Some("root") => root(&args[1..]),
+        Some("prove") => prove(&args[1..]),
+        Some("verify") => verify_proof(&args[1..]),
+        Some("dir-hash") => dir_hash(&args[1..]),
+        Some("dir-verify") => dir_verify(&args[1..]),
+        _ => Err(USAGE.to_string())
+    };
+    match result {
+        Ok(true) => (),
+        Ok(false) => process::exit(1),
+        Err(message) => {
+            eprintln!("{}", message);
+            process::exit(2)
+        }
+    }
+}
+
+fn root(args: &[String]) -> Result<bool, String> {
+    let tree = build_tree(args)?;
+    match tree.root_hash() {
+        Some(hash) => {
+            println!("{}", to_hex(&hash));
+            Ok(true)
+        },
+        None => Err("no records".to_string())
+    }
+}
+
+fn prove(args: &[String]) -> Result<bool, String> {
+    let index = match args.first() {
+        Some(arg) => arg.parse::<usize>().map_err(|_| format!("invalid record number: {}", arg))?,
+        None => return Err(USAGE.to_string())
+    };
+    let tree = build_tree(&args[1..])?;
+    let proof = tree.index_proof_ref(index)
+        .ok_or(format!("no record {}, there are {}", index, tree.leaf_count().unwrap_or(0)))?;
+    for piece in proof {
+        match piece {
+            Proof::Left(hash) => println!("left {}", to_hex(hash)),
+            Proof::Right(hash) => println!("right {}", to_hex(hash))
+        }
+    }
+    Ok(true)
+}
+
+fn verify_proof(args: &[String]) -> Result<bool, String> {
+    let (root, rest) = match args.split_first() {
+        Some((root, rest)) => (parse_hash(root)?, rest),
+        None => return Err(USAGE.to_string())
+    };
+    let (record, rest) = match rest {
+        [flag, path, rest @ ..] if flag == "--file" => (read_file(path)?, rest),
+        [record, rest @ ..] => (record.clone().into_bytes(), rest),
+        [] => return Err(USAGE.to_string())
+    };
+    let proof = match rest {
+        [path] => read_proof(BufReader::new(open(path)?))?,
+        [] => read_proof(BufReader::new(io::stdin()))?,
+        _ => return Err(USAGE.to_string())
+    };
+
+    let hasher_builder = BuildSha256;
+    if verify(&record_hash(&record), &proof, &root, &hasher_builder) {
+        println!("ok");
+        Ok(true)
+    } else {
+        println!("failed");
+        Ok(false)
+    }
+}
+
+fn dir_hash(args: &[String]) -> Result<bool, String> {
+    let dir = match args {
+        [dir] => dir,
+        _ => return Err(USAGE.to_string())
+    };
+    let manifest = manifest::hash_dir(Path::new(dir), &BuildSha256)
+        .map_err(|err| format!("{}: {}", dir, err))?;
+    match manifest.root {
+        Some(ref root) => println!("root {}", to_hex(root)),
+        None => return Err(format!("{}: no files", dir))
+    }
+    for &(ref name, ref hash) in &manifest.files {
+        println!("{}  {}", to_hex(hash), name);
+    }
+    Ok(true)
+}
+
+fn dir_verify(args: &[String]) -> Result<bool, String> {
+    let (dir, path) = match args {
+        [dir, path] => (dir, path),
+        _ => return Err(USAGE.to_string())
+    };
+    let expected = read_manifest(BufReader::new(open(path)?))?;
+    let actual = manifest::hash_dir(Path::new(dir), &BuildSha256)
+        .map_err(|err| format!("{}: {}", dir, err))?;
+
+    let changes = expected.changes(&actual);
+    for change in &changes {
+        match change {
+            &Change::Added(ref name) => println!("added {}", name),
+            &Change::Removed(ref name) => println!("removed {}", name),
+            &Change::Modified(ref name) => println!("modified {}", name)
+        }
+    }
+    if changes.is_empty() && same_root(&expected, &actual) {
+        println!("ok");
+        Ok(true)
+    } else {
+        println!("failed");
+        Ok(false)
+    }
+}
+
+fn same_root(first: &Manifest<Hash>, second: &Manifest<Hash>) -> bool {
+    match (&first.root, &second.root) {
+        (&Some(ref first), &Some(ref second)) => ct_eq(first, second),
+        _ => false
+    }
+}
+
+// Reads a manifest, making sure the listed root matches the listed files
+fn read_manifest<R: BufRead>(reader: R) -> Result<Manifest<Hash>, String> {
+    let mut lines = reader.lines();
+    let root = match lines.next() {
+        Some(Ok(ref line)) if line.starts_with("root ") => parse_hash(&line[5..])?,
+        _ => return Err("manifest must start with a root line".to_string())
+    };
+    let mut files = vec![];
+    for line in lines {
+        let line = line.map_err(|err| err.to_string())?;
+        match line.find("  ") {
+            Some(split) => files.push((line[split + 2..].to_string(), parse_hash(&line[..split])?)),
+            None if line.is_empty() => (),
+            None => return Err(format!("invalid manifest line: {}", line))
+        }
+    }
+    let manifest = manifest::from_files(files, &BuildSha256);
+    if !manifest.root.as_ref().map_or(false, |listed| ct_eq(listed, &root)) {
+        return Err("manifest root does not match its files".to_string())
+    }
+    Ok(manifest)
+}
+
+fn build_tree(args: &[String]) -> Result<Tree, String> {
+    let records = match args {
+        [flag, paths @ ..] if flag == "--files" => {
+            paths.iter()
+                .map(|path| read_file(path))
+                .collect::<Result<Vec<_>, _>>()?
+        },
+        [path] => read_lines(BufReader::new(open(path)?))?,
+        [] => read_lines(BufReader::new(io::stdin()))?,
+        _ => return Err(USAGE.to_string())
+    };
+    let mut tree = HashTree::with_hasher(BuildSha256);
+    tree.insert_hashes(records.iter().map(|record| record_hash(record)));
+    Ok(tree)
+}
+
+fn record_hash(record: &[u8]) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(&(record.len() as u64).to_be_bytes());
+    hasher.update(record);
+    hasher.finalize()
+}
+
+fn open(path: &str) -> Result<File, String> {
+    File::open(path).map_err(|err| format!("{}: {}", path, err))
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>, String> {
+    let mut contents = vec![];
+    open(path)?.read_to_end(&mut contents)
+        .map_err(|err| format!("{}: {}", path, err))?;
+    Ok(contents)
+}
+
+fn read_lines<R: BufRead>(reader: R) -> Result<Vec<Vec<u8>>, String> {
+    reader.split(b'\n')
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| err.to_string())
+}
+
+fn read_proof<R: BufRead>(reader: R) -> Result<Vec<Proof<Hash>>, String> {
+    let mut proof = vec![];
+    for line in reader.lines() {
+        let line = line.map_err(|err| err.to_string())?;
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some("left"), Some(hash)) => proof.push(Proof::Left(parse_hash(hash)?)),
+            (Some("right"), Some(hash)) => proof.push(Proof::Right(parse_hash(hash)?)),
+            (None, _) => (),
+            _ => return Err(format!("invalid proof line: {}", line))
+        }
+    }
+    Ok(proof)
+}
+
+fn parse_hash(hex: &str) -> Result<Hash, String> {
+    hex.parse::<Hex<Hash>>()
+        .map(Hex::into_inner)
+        .map_err(|err| format!("invalid hash {}: {}", hex, err))
+}
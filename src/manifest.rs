@@ -0,0 +1,149 @@
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::hash::Hasher;
+use std::collections::BTreeMap;
+
+use hash::{BuildMerkleHasher, MerkleHasher, Digest};
+use node::HashValue;
+use tree::HashTree;
+
+
+// Manifest of a directory: a tree with one leaf per regular file.
+//
+// Files are ordered by their '/'-separated path relative to the
+//  directory, compared bytewise, so the root doesn't depend on the
+//  order read_dir() lists entries in.
+// Symlinks are skipped.
+//
+// A file's leaf is the hash of its path and its contents, each as
+//  a big-endian u64 length followed by the bytes. Contents are
+//  streamed rather than read into memory.
+// Leaves don't depend on the platform. Roots don't either as long
+//  as the hasher's hash_branch() doesn't, which holds for BuildSha256
+//  but not for the default hash_branch() (it adds usize lengths).
+
+pub struct Manifest<H> {
+    pub root: Option<H>,
+    pub files: Vec<(String, H)>
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    Added(String),
+    Removed(String),
+    Modified(String)
+}
+
+pub fn hash_dir<S>(dir: &Path, hasher_builder: &S) -> io::Result<Manifest<HashValue<S>>>
+where
+    S: BuildMerkleHasher
+{
+    let mut paths = vec![];
+    collect_files(dir, dir, &mut paths)?;
+    let mut files = Vec::with_capacity(paths.len());
+    for (name, path) in paths {
+        let hash = hash_file(&name, &path, hasher_builder)?;
+        files.push((name, hash));
+    }
+    Ok(from_files(files, hasher_builder))
+}
+
+// Builds a manifest from known leaf hashes, e.g. to check that
+//  a stored manifest's root matches its entries
+pub fn from_files<S>(mut files: Vec<(String, HashValue<S>)>, hasher_builder: &S) -> Manifest<HashValue<S>>
+where
+    S: BuildMerkleHasher
+{
+    files.sort_by(|first, second| first.0.cmp(&second.0));
+    let mut tree: HashTree<S> = HashTree::with_hasher(hasher_builder.clone());
+    tree.insert_hashes(files.iter().map(|&(_, ref hash)| hash.clone()));
+    Manifest {
+        root: tree.root().as_ref().map(|root| root.hash_value()),
+        files: files
+    }
+}
+
+impl<H> Manifest<H>
+where
+    H: Digest
+{
+    // What happened to the files of this manifest to get to `actual`,
+    //  in path order
+    pub fn changes(&self, actual: &Manifest<H>) -> Vec<Change> {
+        let expected: BTreeMap<&str, &H> = self.files.iter()
+            .map(|&(ref name, ref hash)| (name.as_str(), hash))
+            .collect();
+        let found: BTreeMap<&str, &H> = actual.files.iter()
+            .map(|&(ref name, ref hash)| (name.as_str(), hash))
+            .collect();
+
+        let mut names: Vec<&str> = expected.keys().chain(found.keys()).cloned().collect();
+        names.sort();
+        names.dedup();
+        names.into_iter()
+            .filter_map(|name| match (expected.get(name), found.get(name)) {
+                (Some(first), Some(second)) if first != second => Some(Change::Modified(name.to_string())),
+                (Some(_), None) => Some(Change::Removed(name.to_string())),
+                (None, Some(_)) => Some(Change::Added(name.to_string())),
+                _ => None
+            })
+            .collect()
+    }
+}
+
+fn collect_files(root: &Path, dir: &Path, files: &mut Vec<(String, PathBuf)>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let path = entry.path();
+        if file_type.is_dir() {
+            collect_files(root, &path, files)?;
+        } else if file_type.is_file() {
+            let name = relative_name(root, &path)
+                .ok_or(io::Error::new(io::ErrorKind::InvalidData, format!("unsupported path: {}", path.display())))?;
+            files.push((name, path));
+        }
+    }
+    Ok(())
+}
+
+fn relative_name(root: &Path, path: &Path) -> Option<String> {
+    let components = path.strip_prefix(root).ok()?
+        .components()
+        .map(|component| component.as_os_str().to_str())
+        .collect::<Option<Vec<_>>>()?;
+    let name = components.join("/");
+    if name.contains('\n') {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+fn hash_file<S>(name: &str, path: &Path, hasher_builder: &S) -> io::Result<HashValue<S>>
+where
+    S: BuildMerkleHasher
+{
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut hasher = hasher_builder.build_hasher();
+    hasher.write(&(name.len() as u64).to_be_bytes());
+    hasher.write(name.as_bytes());
+    hasher.write(&len.to_be_bytes());
+
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut read = 0;
+    loop {
+        let count = file.read(&mut buffer)?;
+        if count == 0 {
+            break
+        }
+        hasher.write(&buffer[..count]);
+        read += count as u64;
+    }
+    if read != len {
+        return Err(io::Error::new(io::ErrorKind::Other, format!("{} changed while hashing", path.display())))
+    }
+    Ok(hasher.finish_full())
+}
@@ -4,11 +4,15 @@ use std::fmt;
 
 use hash::MerkleHasher;
 use hash::BuildMerkleHasher;
+use hex::to_hex;
 
 
 // Node is either a leaf that holds a value
 //  or a branch that holds hash of its children
 //
+// Leaves can also be built straight from a precomputed hash,
+//  in which case they hold no value at all.
+//
 // Nodes are immutable.
 // Node enum is the main interface to nodes, 
 //  Branch and Leaf struct methods should only be used when
@@ -19,9 +23,12 @@ use hash::BuildMerkleHasher;
 //
 // Siblingless leaves in a node are hashed twice (!!!)
 //  to substitute the missing sibling
+//
+// Branches cache their height, leaf count and finality,
+//  so none of these walk the subtree.
 
 pub type Child<V, S> = Box<Node<V, S>>;
-pub type HashValue = Box<[u8]>;
+pub type HashValue<S> = <<S as BuildMerkleHasher>::Hasher as MerkleHasher>::Output;
 
 #[derive(Clone)]
 pub enum Node<V, S>
@@ -39,9 +46,9 @@ where
     V: Hash,
     S: BuildMerkleHasher
 {
-    value: V,
+    value: Option<V>,
     hasher_builder: S,
-    hash: Option<HashValue>
+    hash: Option<HashValue<S>>
 }
 
 #[derive(Clone)]
@@ -53,7 +60,10 @@ where
     left: Option<Child<V, S>>,
     right: Option<Child<V, S>>,
     hasher_builder: S,
-    hash: Option<HashValue>          
+    hash: Option<HashValue<S>>,
+    leaf_count: usize,
+    height: usize,
+    is_final: bool
 }
 
 impl<V, S> Node<V, S>
@@ -73,13 +83,25 @@ where
         Node::Leaf(Leaf::new(value, hasher_builder))
     }
 
-    pub fn hash_value(&self) -> Box<[u8]> {
+    pub fn new_hash_leaf(hash: HashValue<S>, hasher_builder: S) -> Node<V, S> {
+        Node::Leaf(Leaf::from_hash(hash, hasher_builder))
+    }
+
+    pub fn hash_value(&self) -> HashValue<S> {
         match self {
             &Node::Leaf(ref leaf) => leaf.hash_value(),
             &Node::Branch(ref branch) => branch.hash_value()
         }
     }
 
+    // Borrowed alternative to hash_value(), doesn't allocate
+    pub fn hash_ref(&self) -> &[u8] {
+        match self {
+            &Node::Leaf(ref leaf) => leaf.hash_ref(),
+            &Node::Branch(ref branch) => branch.hash_ref()
+        }
+    }
+
     pub fn leaf_count(&self) -> usize {
         match self {
             &Node::Leaf(_) => 1,
@@ -114,6 +136,21 @@ where
             _ => &None
         }
     }
+
+    // Takes the node apart, leaves have no children
+    pub fn into_children(self) -> (Option<Child<V, S>>, Option<Child<V, S>>) {
+        match self {
+            Node::Branch(branch) => branch.into_children(),
+            _ => (None, None)
+        }
+    }
+
+    pub fn value(&self) -> Option<&V> {
+        match self {
+            &Node::Leaf(ref leaf) => leaf.value(),
+            _ => None
+        }
+    }
 }
 
 impl<V, S> Hash for Node<V, S>
@@ -136,7 +173,7 @@ where
 {
     pub fn new(value: V, hasher_builder: S) -> Leaf<V, S> {
         let mut leaf = Leaf {
-            value: value,
+            value: Some(value),
             hasher_builder: hasher_builder,
             hash: None
         };
@@ -146,7 +183,16 @@ where
         leaf
     }
 
-    pub fn hash_value(&self) -> Box<[u8]> {
+    // Leaf hash is taken as is, without hashing it again
+    pub fn from_hash(hash: HashValue<S>, hasher_builder: S) -> Leaf<V, S> {
+        Leaf {
+            value: None,
+            hasher_builder: hasher_builder,
+            hash: Some(hash)
+        }
+    }
+
+    pub fn hash_value(&self) -> HashValue<S> {
         if let Some(ref hash) = self.hash {
             hash.clone()
         } else {
@@ -154,9 +200,21 @@ where
         }
     }
 
+    pub fn hash_ref(&self) -> &[u8] {
+        if let Some(ref hash) = self.hash {
+            hash.as_ref()
+        } else {
+            panic!("Hash was not set for leaf object")
+        }
+    }
+
     pub fn hasher_builder(&self) -> &S {
         &self.hasher_builder
     }
+
+    pub fn value(&self) -> Option<&V> {
+        self.value.as_ref()
+    }
 }
 
 impl<V, S> Hash for Leaf<V, S>
@@ -165,7 +223,9 @@ where
     S: BuildMerkleHasher
 {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        self.value.hash(state)
+        if let Some(ref value) = self.value {
+            value.hash(state)
+        }
     }
 }
 
@@ -175,19 +235,49 @@ where
     S: BuildMerkleHasher
 {
     pub fn new(left: Option<Child<V, S>>, right: Option<Child<V, S>>, hasher_builder: S) -> Branch<V, S> {
+        let leaf_count = match (&left, &right) {
+            (&Some(ref left), &Some(ref right)) => left.leaf_count() + right.leaf_count(),
+            (&Some(ref left), _) => left.leaf_count(),
+            (_, &Some(ref right)) => right.leaf_count(),
+            _ => 0
+        };
+        let height = match (&left, &right) {
+            (&Some(ref left), &Some(ref right)) => bigger(left.height(), right.height()) + 1,
+            (&Some(ref left), _) => left.height() + 1,
+            (_, &Some(ref right)) => right.height() + 1,
+            _ => 0
+        };
+        let is_final = match (&left, &right) {
+            (&Some(ref left), &Some(ref right)) => left.is_final() && right.is_final(),
+            _ => false
+        };
         let mut branch = Branch {
             left: left,
             right: right,
             hasher_builder: hasher_builder,
-            hash: None
+            hash: None,
+            leaf_count: leaf_count,
+            height: height,
+            is_final: is_final
         };
-        let mut hasher = branch.hasher_builder().build_hasher();
-        branch.hash(&mut hasher);
-        branch.hash = Some(hasher.finish_full());
+        branch.hash = Some(match branch.children_hashes() {
+            Some((left, right)) => branch.hasher_builder.hash_branch(left, right),
+            None => branch.hasher_builder.build_hasher().finish_full()
+        });
         branch
     }
 
-    pub fn hash_value(&self) -> Box<[u8]> {
+    // Hashes the branch is made of, the only child's twice
+    fn children_hashes(&self) -> Option<(&[u8], &[u8])> {
+        match (&self.left, &self.right) {
+            (&Some(ref left), &Some(ref right)) => Some((left.hash_ref(), right.hash_ref())),
+            (&Some(ref left), _) => Some((left.hash_ref(), left.hash_ref())),
+            (_, &Some(ref right)) => Some((right.hash_ref(), right.hash_ref())),
+            _ => None
+        }
+    }
+
+    pub fn hash_value(&self) -> HashValue<S> {
         if let Some(ref hash) = self.hash {
             hash.clone()
         } else {
@@ -195,33 +285,28 @@ where
         }
     }
 
+    pub fn hash_ref(&self) -> &[u8] {
+        if let Some(ref hash) = self.hash {
+            hash.as_ref()
+        } else {
+            panic!("Hash was not set for leaf object")
+        }
+    }
+
     pub fn hasher_builder(&self) -> &S {
         &self.hasher_builder
     }
 
     pub fn leaf_count(&self) -> usize {
-        match (&self.left, &self.right) {
-            (&Some(ref left), &Some(ref right)) => left.leaf_count() + right.leaf_count(),
-            (&Some(ref left), _) => left.leaf_count(),
-            (_, &Some(ref right)) => right.leaf_count(),
-            _ => 0
-        }
+        self.leaf_count
     }
 
     pub fn height(&self) -> usize {
-        match (&self.left, &self.right) {
-            (&Some(ref left), &Some(ref right)) => bigger(left.height(), right.height()) + 1,
-            (&Some(ref left), _) => left.height() + 1,
-            (_, &Some(ref right)) => right.height() + 1,
-            _ => 0
-        }
+        self.height
     }
 
     pub fn is_final(&self) -> bool {
-        match (&self.left, &self.right) {
-            (&Some(ref left), &Some(ref right)) => left.is_final() && right.is_final(),
-            _ => false
-        }
+        self.is_final
     }
 
     pub fn left(&self) -> &Option<Child<V, S>> {
@@ -231,6 +316,10 @@ where
     pub fn right(&self) -> &Option<Child<V, S>> {
         &self.right
     }
+
+    pub fn into_children(self) -> (Option<Child<V, S>>, Option<Child<V, S>>) {
+        (self.left, self.right)
+    }
 }
 
 impl<V, S> Hash for Branch<V, S>
@@ -239,20 +328,9 @@ where
     S: BuildMerkleHasher
 {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        match (&self.left, &self.right) {
-            (&Some(ref left), &Some(ref right)) => {
-                left.hash_value().as_ref().hash(state);
-                right.hash_value().as_ref().hash(state)
-            },
-            (&Some(ref left), _) => {
-                left.hash_value().as_ref().hash(state);                
-                left.hash_value().as_ref().hash(state);                                
-            },
-            (_, &Some(ref right)) => {
-                right.hash_value().as_ref().hash(state);                
-                right.hash_value().as_ref().hash(state);                                              
-            },
-            _ => ()
+        if let Some((left, right)) = self.children_hashes() {
+            left.hash(state);
+            right.hash(state);
         }
     }
 }
@@ -271,20 +349,12 @@ where
     S: BuildMerkleHasher 
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for &byte in self.hash_value().as_ref() {
-            write!(f, "{:X}", byte)?;
-        }
+        write!(f, "{}", to_hex(self.hash_ref()))?;
         if let &Some(ref left) = self.left() {
-            write!(f, "\nLeft: ");            
-            for &byte in left.hash_value().as_ref() {
-                write!(f, "{:X}", byte)?;
-            }
+            write!(f, "\nLeft: {}", to_hex(left.hash_ref()))?;
         };
         if let &Some(ref right) = self.right() {
-            write!(f, "\nRight: ");                        
-            for &byte in right.hash_value().as_ref() {
-                write!(f, "{:X}", byte)?;
-            }
+            write!(f, "\nRight: {}", to_hex(right.hash_ref()))?;
         };
         if let &Some(ref left) = self.left() {
             write!(f, "\n");            
@@ -0,0 +1,205 @@
+use std::hash::Hash;
+use std::hash::Hasher;
+
+use hash::{MerkleHasher, BuildMerkleHasher};
+use node::HashValue;
+use tree::{MerkleTree, Proof};
+use hex::ct_eq;
+
+
+// Sorted-pair Merkle trees, as checked by OpenZeppelin's MerkleProof
+//  library in Solidity.
+//
+// SortedPairs wraps a hasher so that branches hash the concatenation
+//  of their children's hashes smallest first, with no length prefixes.
+//  Proofs then need no directions: a proof is the list of sibling
+//  hashes from the leaf up, which is the bytes32[] the contracts take.
+//  With SortedPairs(BuildKeccak256), roots and proofs are what
+//  MerkleProof computes on chain.
+//
+// StandardMerkleTree (OpenZeppelin's JS library) hashes its leaves
+//  twice, keccak256(keccak256(abi.encode(values))), so that a leaf
+//  can't pass for a branch: see standard_leaf_hash(), the encoding is
+//  up to the caller. It also sorts leaves by hash and lays out trees
+//  whose size isn't a power of two differently, so for those sizes its
+//  root differs from ours; the contracts accept both, as they only
+//  check a proof against a root.
+//
+// Multiproofs prove several leaves at once, in the form
+//  multiProofVerify() takes: the leaves, the extra hashes needed and a
+//  flag per hash computed saying whether its second input comes from
+//  the leaves and hashes computed so far (true) or from the proof.
+
+#[derive(Clone, Default)]
+pub struct SortedPairs<S>(pub S);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MultiProof<H> {
+    pub leaves: Vec<H>,
+    pub proof: Vec<H>,
+    pub proof_flags: Vec<bool>
+}
+
+impl<S> BuildMerkleHasher for SortedPairs<S>
+where
+    S: BuildMerkleHasher
+{
+    type Hasher = S::Hasher;
+
+    fn build_hasher(&self) -> S::Hasher {
+        self.0.build_hasher()
+    }
+
+    fn hash_branch(&self, left: &[u8], right: &[u8]) -> HashValue<S> {
+        let (first, second) = if left <= right { (left, right) } else { (right, left) };
+        let mut hasher = self.0.build_hasher();
+        hasher.write(first);
+        hasher.write(second);
+        hasher.finish_full()
+    }
+}
+
+// Leaf hash of StandardMerkleTree for an already encoded value
+pub fn standard_leaf_hash<S>(encoded: &[u8], hasher_builder: &S) -> HashValue<S>
+where
+    S: BuildMerkleHasher
+{
+    let mut hasher = hasher_builder.build_hasher();
+    hasher.write(encoded);
+    let inner = hasher.finish_full();
+    let mut hasher = hasher_builder.build_hasher();
+    hasher.write(inner.as_ref());
+    hasher.finish_full()
+}
+
+// Sibling hashes from the leaf at `index` up to the root
+pub fn proof<V, S>(tree: &MerkleTree<V, SortedPairs<S>>, index: usize) -> Option<Vec<HashValue<S>>>
+where
+    V: Hash + Clone,
+    S: BuildMerkleHasher
+{
+    let proof = tree.index_proof(index)?;
+    Some(proof.into_iter().map(|piece| match piece {
+        Proof::Left(hash) => hash,
+        Proof::Right(hash) => hash
+    }).collect())
+}
+
+// Root a proof leads to, like MerkleProof.processProof()
+pub fn process_proof<S>(leaf: &HashValue<S>, proof: &[HashValue<S>], hasher_builder: &SortedPairs<S>) -> HashValue<S>
+where
+    S: BuildMerkleHasher
+{
+    proof.iter().fold(leaf.clone(), |current, sibling| {
+        hasher_builder.hash_branch(current.as_ref(), sibling.as_ref())
+    })
+}
+
+pub fn verify<S>(leaf: &HashValue<S>, proof: &[HashValue<S>], root: &HashValue<S>, hasher_builder: &SortedPairs<S>) -> bool
+where
+    S: BuildMerkleHasher
+{
+    ct_eq(process_proof(leaf, proof, hasher_builder).as_ref(), root.as_ref())
+}
+
+// Multiproof for the leaves at the given positions, in any order and
+//  possibly repeated. Leaves come out in position order.
+pub fn multi_proof<V, S>(tree: &MerkleTree<V, SortedPairs<S>>, indices: &[usize]) -> Option<MultiProof<HashValue<S>>>
+where
+    V: Hash + Clone,
+    S: BuildMerkleHasher
+{
+    let mut known = indices.to_vec();
+    known.sort();
+    known.dedup();
+    let leaf_count = tree.leaf_count()?;
+    if known.last().map_or(false, |&last| last >= leaf_count) {
+        return None
+    }
+    let node_hash = |level, index| tree.node_at(level, index).map(|node| node.hash_value());
+    let mut multi_proof = MultiProof {
+        leaves: known.iter().map(|&index| node_hash(0, index)).collect::<Option<_>>()?,
+        proof: vec![],
+        proof_flags: vec![]
+    };
+    if known.is_empty() {
+        multi_proof.proof.push(tree.root_hash()?);
+        return Some(multi_proof)
+    }
+
+    // Every level is hashed left to right, so hashes are taken from
+    //  the queue in the order they were computed
+    for level in 0..tree.height() {
+        let width = (leaf_count + (1 << level) - 1) >> level;
+        let mut parents = vec![];
+        let mut position = 0;
+        while position < known.len() {
+            let index = known[position];
+            let sibling = index ^ 1;
+            if position + 1 < known.len() && known[position + 1] == sibling {
+                multi_proof.proof_flags.push(true);
+                position += 1;
+            } else {
+                // A lonely node is paired with itself
+                let sibling = if sibling < width { sibling } else { index };
+                multi_proof.proof.push(node_hash(level, sibling)?);
+                multi_proof.proof_flags.push(false);
+            }
+            parents.push(index / 2);
+            position += 1;
+        }
+        known = parents;
+    }
+    Some(multi_proof)
+}
+
+// Root a multiproof leads to, like MerkleProof.processMultiProof().
+// None if the parts don't add up.
+pub fn process_multi_proof<S>(multi_proof: &MultiProof<HashValue<S>>, hasher_builder: &SortedPairs<S>) -> Option<HashValue<S>>
+where
+    S: BuildMerkleHasher
+{
+    let leaves = &multi_proof.leaves;
+    let proof = &multi_proof.proof;
+    let flags = &multi_proof.proof_flags;
+    if leaves.len() + proof.len() != flags.len() + 1 {
+        return None
+    }
+    let mut hashes: Vec<HashValue<S>> = Vec::with_capacity(flags.len());
+    let (mut leaf_position, mut hash_position, mut proof_position) = (0, 0, 0);
+    for &flag in flags {
+        let mut next = || {
+            if leaf_position < leaves.len() {
+                leaf_position += 1;
+                leaves.get(leaf_position - 1).cloned()
+            } else {
+                hash_position += 1;
+                hashes.get(hash_position - 1).cloned()
+            }
+        };
+        let first = next()?;
+        let second = if flag {
+            next()?
+        } else {
+            proof_position += 1;
+            proof.get(proof_position - 1)?.clone()
+        };
+        hashes.push(hasher_builder.hash_branch(first.as_ref(), second.as_ref()));
+    }
+    if !flags.is_empty() {
+        if proof_position != proof.len() {
+            return None
+        }
+        hashes.pop()
+    } else {
+        leaves.first().or(proof.first()).cloned()
+    }
+}
+
+pub fn verify_multi_proof<S>(multi_proof: &MultiProof<HashValue<S>>, root: &HashValue<S>, hasher_builder: &SortedPairs<S>) -> bool
+where
+    S: BuildMerkleHasher
+{
+    process_multi_proof(multi_proof, hasher_builder)
+        .map_or(false, |computed| ct_eq(computed.as_ref(), root.as_ref()))
+}
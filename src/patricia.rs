@@ -0,0 +1,450 @@
+use std::hash::Hasher;
+
+use hash::{MerkleHasher, BuildMerkleHasher, Digest};
+use node::HashValue;
+use rlp::{self, Rlp};
+use hex::ct_eq;
+
+
+// Hexary Merkle Patricia Trie, as Ethereum keeps its state, storage,
+//  transactions and receipts in.
+//
+// Keys are walked a nibble (half byte) at a time. Nodes are:
+//
+//  leaf         rest of the key, value
+//  extension    nibbles shared by every key below, branch
+//  branch       16 children, one per next nibble, and the value of
+//                the key that ends here, if any
+//
+// Every key has a single place, so the trie has the same shape and
+//  root whatever order keys were inserted in; delete() merges nodes
+//  back as if the key was never there. Values can't be empty:
+//  inserting an empty value deletes the key, as in Ethereum.
+//
+// Nodes are RLP lists ([hex-prefix path, value], [hex-prefix path,
+//  child], [16 children, value]). A node refers to a child by its hash,
+//  or contains the child's encoding when that is shorter than 32 bytes.
+//  The root is the hash of the root node's encoding, keccak256 with
+//  Ethereum; the hasher is fed raw bytes, so any MerkleHasher works.
+//
+// Keys are used as given. Ethereum's state and storage tries use the
+//  keccak256 of the address or slot as the key, hash those first.
+//
+// proof() gives the encoded nodes on the path to a key, root first:
+//  the accountProof and storageProof lists of eth_getProof. They prove
+//  the key's value, or that there's none, see verify_proof().
+
+const EMBED_LIMIT: usize = 32;
+
+pub struct PatriciaTrie<S>
+where
+    S: BuildMerkleHasher
+{
+    root: Option<Box<TrieNode>>,
+    hasher_builder: S,
+    len: usize
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum TrieNode {
+    // Rest of the key in nibbles, value
+    Leaf(Vec<u8>, Vec<u8>),
+    // Shared nibbles, never empty, and the branch below them
+    Extension(Vec<u8>, Box<TrieNode>),
+    Branch([Option<Box<TrieNode>>; 16], Option<Vec<u8>>)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TrieError {
+    // Proof ends before the path does
+    MissingNode(usize),
+    // Node doesn't hash to what its parent (or the root) says
+    HashMismatch(usize),
+    // Node isn't a valid trie node
+    BadNode(usize),
+    // Proof goes on after the path ended
+    UnusedNodes
+}
+
+impl<S> PatriciaTrie<S>
+where
+    S: BuildMerkleHasher
+{
+    pub fn new(hasher_builder: S) -> PatriciaTrie<S> {
+        PatriciaTrie {
+            root: None,
+            hasher_builder: hasher_builder,
+            len: 0
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        let path = nibbles(key);
+        let mut path = &path[..];
+        let mut node = self.root.as_ref()?;
+        loop {
+            match &**node {
+                &TrieNode::Leaf(ref rest, ref value) => {
+                    return if rest[..] == path[..] { Some(value) } else { None }
+                },
+                &TrieNode::Extension(ref shared, ref child) => {
+                    if !path.starts_with(shared) {
+                        return None
+                    }
+                    path = &path[shared.len()..];
+                    node = child;
+                },
+                &TrieNode::Branch(ref children, ref value) => match path.split_first() {
+                    None => return value.as_ref().map(|value| &value[..]),
+                    Some((&nibble, rest)) => {
+                        node = children[nibble as usize].as_ref()?;
+                        path = rest;
+                    }
+                }
+            }
+        }
+    }
+
+    // Sets the value of a key, returns the one it replaces
+    pub fn insert(&mut self, key: &[u8], value: Vec<u8>) -> Option<Vec<u8>> {
+        if value.is_empty() {
+            return self.delete(key)
+        }
+        let mut old = None;
+        self.root = Some(insert_node(self.root.take(), &nibbles(key), value, &mut old));
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    pub fn delete(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        let mut removed = None;
+        self.root = match self.root.take() {
+            Some(root) => delete_node(root, &nibbles(key), &mut removed),
+            None => None
+        };
+        if removed.is_some() {
+            self.len -= 1;
+        }
+        removed
+    }
+
+    // Hash of the root node, that of the empty string's encoding for
+    //  an empty trie
+    pub fn root_hash(&self) -> HashValue<S> {
+        match self.root {
+            Some(ref root) => self.hash(&self.encode(root)),
+            None => self.hash(&rlp::encode_bytes(&[]))
+        }
+    }
+
+    // Encoded nodes from the root to where the key's path ends, leaving
+    //  out nodes embedded in their parent. Empty for an empty trie.
+    pub fn proof(&self, key: &[u8]) -> Vec<Vec<u8>> {
+        let mut proof = vec![];
+        let path = nibbles(key);
+        let mut path = &path[..];
+        let mut node = match self.root {
+            Some(ref root) => root,
+            None => return proof
+        };
+        loop {
+            let encoded = self.encode(node);
+            if proof.is_empty() || encoded.len() >= EMBED_LIMIT {
+                proof.push(encoded);
+            }
+            node = match &**node {
+                &TrieNode::Leaf(..) => return proof,
+                &TrieNode::Extension(ref shared, ref child) => {
+                    if !path.starts_with(shared) {
+                        return proof
+                    }
+                    path = &path[shared.len()..];
+                    child
+                },
+                &TrieNode::Branch(ref children, _) => match path.split_first() {
+                    Some((&nibble, rest)) if children[nibble as usize].is_some() => {
+                        path = rest;
+                        children[nibble as usize].as_ref().unwrap()
+                    },
+                    _ => return proof
+                }
+            };
+        }
+    }
+
+    pub fn hasher_builder(&self) -> S {
+        self.hasher_builder.clone()
+    }
+
+    fn encode(&self, node: &TrieNode) -> Vec<u8> {
+        match node {
+            &TrieNode::Leaf(ref rest, ref value) => rlp::encode_list(&[
+                rlp::encode_bytes(&hex_prefix(rest, true)),
+                rlp::encode_bytes(value)
+            ]),
+            &TrieNode::Extension(ref shared, ref child) => rlp::encode_list(&[
+                rlp::encode_bytes(&hex_prefix(shared, false)),
+                self.reference(child)
+            ]),
+            &TrieNode::Branch(ref children, ref value) => {
+                let mut items: Vec<Vec<u8>> = children.iter()
+                    .map(|child| match child {
+                        &Some(ref child) => self.reference(child),
+                        &None => rlp::encode_bytes(&[])
+                    })
+                    .collect();
+                items.push(rlp::encode_bytes(value.as_ref().map_or(&[][..], |value| &value[..])));
+                rlp::encode_list(&items)
+            }
+        }
+    }
+
+    // How a parent refers to a node: its hash, or the node itself if short
+    fn reference(&self, node: &TrieNode) -> Vec<u8> {
+        let encoded = self.encode(node);
+        if encoded.len() < EMBED_LIMIT {
+            encoded
+        } else {
+            rlp::encode_bytes(self.hash(&encoded).as_ref())
+        }
+    }
+
+    fn hash(&self, bytes: &[u8]) -> HashValue<S> {
+        hash_bytes(bytes, &self.hasher_builder)
+    }
+}
+
+// Value of `key` in the trie with the given root, None if the proof
+//  shows it has none
+pub fn verify_proof<S>(
+    root_hash: &HashValue<S>,
+    key: &[u8],
+    proof: &[Vec<u8>],
+    hasher_builder: &S) -> Result<Option<Vec<u8>>, TrieError>
+where
+    S: BuildMerkleHasher
+{
+    // Nothing to show for the empty trie
+    let empty_root = hash_bytes(&rlp::encode_bytes(&[]), hasher_builder);
+    if proof.is_empty() && ct_eq(root_hash.as_ref(), empty_root.as_ref()) {
+        return Ok(None)
+    }
+    let path = nibbles(key);
+    let mut path = &path[..];
+    let mut used = 0;
+    let mut node = hashed_node(root_hash, proof, &mut used, hasher_builder)?;
+    let value = loop {
+        // Embedded nodes count as part of the last hashed one
+        let current = used - 1;
+        let items = node.as_list().ok_or(TrieError::BadNode(current))?;
+        let child = match items.len() {
+            17 => match path.split_first() {
+                None => break items[16].as_bytes().ok_or(TrieError::BadNode(current))?,
+                Some((&nibble, rest)) => {
+                    path = rest;
+                    &items[nibble as usize]
+                }
+            },
+            2 => {
+                let (shared, is_leaf) = items[0].as_bytes()
+                    .and_then(from_hex_prefix)
+                    .ok_or(TrieError::BadNode(current))?;
+                if is_leaf {
+                    let value = items[1].as_bytes().ok_or(TrieError::BadNode(current))?;
+                    break if shared[..] == path[..] { value } else { &[][..] }
+                }
+                if !path.starts_with(&shared) {
+                    break &[][..]
+                }
+                path = &path[shared.len()..];
+                &items[1]
+            },
+            _ => return Err(TrieError::BadNode(current))
+        };
+        node = match child {
+            &Rlp::List(_) => child.clone(),
+            &Rlp::Bytes(bytes) if bytes.is_empty() => break &[][..],
+            &Rlp::Bytes(bytes) => {
+                let hash = HashValue::<S>::from_bytes(bytes).ok_or(TrieError::BadNode(current))?;
+                hashed_node(&hash, proof, &mut used, hasher_builder)?
+            }
+        };
+    };
+    if used != proof.len() {
+        return Err(TrieError::UnusedNodes)
+    }
+    Ok(if value.is_empty() { None } else { Some(value.to_vec()) })
+}
+
+// Next node of the proof, which must have the given hash
+fn hashed_node<'a, S>(hash: &HashValue<S>, proof: &'a [Vec<u8>], used: &mut usize, hasher_builder: &S) -> Result<Rlp<'a>, TrieError>
+where
+    S: BuildMerkleHasher
+{
+    let index = *used;
+    let encoded = proof.get(index).ok_or(TrieError::MissingNode(index))?;
+    if !ct_eq(hash_bytes(encoded, hasher_builder).as_ref(), hash.as_ref()) {
+        return Err(TrieError::HashMismatch(index))
+    }
+    *used += 1;
+    rlp::decode(encoded).map_err(|_| TrieError::BadNode(index))
+}
+
+fn hash_bytes<S>(bytes: &[u8], hasher_builder: &S) -> HashValue<S>
+where
+    S: BuildMerkleHasher
+{
+    let mut hasher = hasher_builder.build_hasher();
+    hasher.write(bytes);
+    hasher.finish_full()
+}
+
+fn insert_node(node: Option<Box<TrieNode>>, path: &[u8], value: Vec<u8>, old: &mut Option<Vec<u8>>) -> Box<TrieNode> {
+    let node = match node {
+        Some(node) => node,
+        None => return Box::new(TrieNode::Leaf(path.to_vec(), value))
+    };
+    match *node {
+        TrieNode::Leaf(rest, leaf_value) => {
+            if rest[..] == path[..] {
+                *old = Some(leaf_value);
+                return Box::new(TrieNode::Leaf(rest, value))
+            }
+            // Split into a branch where the two keys part
+            let common = common_prefix(&rest, path);
+            let branch = insert_node(Some(empty_branch()), &rest[common..], leaf_value, old);
+            let branch = insert_node(Some(branch), &path[common..], value, old);
+            with_prefix(&path[..common], branch)
+        },
+        TrieNode::Extension(shared, child) => {
+            let common = common_prefix(&shared, path);
+            if common == shared.len() {
+                let child = insert_node(Some(child), &path[common..], value, old);
+                return with_prefix(&shared, child)
+            }
+            let mut branch = empty_branch();
+            if let TrieNode::Branch(ref mut children, _) = *branch {
+                children[shared[common] as usize] = Some(with_prefix(&shared[common + 1..], child));
+            }
+            let branch = insert_node(Some(branch), &path[common..], value, old);
+            with_prefix(&path[..common], branch)
+        },
+        TrieNode::Branch(mut children, mut branch_value) => {
+            match path.split_first() {
+                None => *old = branch_value.replace(value),
+                Some((&nibble, rest)) => {
+                    let child = children[nibble as usize].take();
+                    children[nibble as usize] = Some(insert_node(child, rest, value, old));
+                }
+            }
+            Box::new(TrieNode::Branch(children, branch_value))
+        }
+    }
+}
+
+fn delete_node(node: Box<TrieNode>, path: &[u8], removed: &mut Option<Vec<u8>>) -> Option<Box<TrieNode>> {
+    match *node {
+        TrieNode::Leaf(rest, value) => {
+            if rest[..] == path[..] {
+                *removed = Some(value);
+                return None
+            }
+            Some(Box::new(TrieNode::Leaf(rest, value)))
+        },
+        TrieNode::Extension(shared, child) => {
+            if !path.starts_with(&shared) {
+                return Some(Box::new(TrieNode::Extension(shared, child)))
+            }
+            delete_node(child, &path[shared.len()..], removed)
+                .map(|child| with_prefix(&shared, child))
+        },
+        TrieNode::Branch(mut children, mut value) => {
+            match path.split_first() {
+                None => *removed = value.take(),
+                Some((&nibble, rest)) => {
+                    if let Some(child) = children[nibble as usize].take() {
+                        children[nibble as usize] = delete_node(child, rest, removed);
+                    }
+                }
+            }
+            collapse(children, value)
+        }
+    }
+}
+
+// Branch with what's left of its entries, merged into a leaf or an
+//  extension when it's down to one
+fn collapse(mut children: [Option<Box<TrieNode>>; 16], value: Option<Vec<u8>>) -> Option<Box<TrieNode>> {
+    let used: Vec<usize> = (0..16).filter(|&nibble| children[nibble].is_some()).collect();
+    match (used.len(), value) {
+        (0, None) => None,
+        (0, Some(value)) => Some(Box::new(TrieNode::Leaf(vec![], value))),
+        (1, None) => {
+            let child = children[used[0]].take().unwrap();
+            Some(with_prefix(&[used[0] as u8], child))
+        },
+        (_, value) => Some(Box::new(TrieNode::Branch(children, value)))
+    }
+}
+
+// Node under the given nibbles, merged into it if it's a leaf or an
+//  extension
+fn with_prefix(prefix: &[u8], node: Box<TrieNode>) -> Box<TrieNode> {
+    if prefix.is_empty() {
+        return node
+    }
+    match *node {
+        TrieNode::Leaf(rest, value) => Box::new(TrieNode::Leaf([prefix, &rest[..]].concat(), value)),
+        TrieNode::Extension(shared, child) => Box::new(TrieNode::Extension([prefix, &shared[..]].concat(), child)),
+        branch => Box::new(TrieNode::Extension(prefix.to_vec(), Box::new(branch)))
+    }
+}
+
+fn empty_branch() -> Box<TrieNode> {
+    Box::new(TrieNode::Branch(Default::default(), None))
+}
+
+fn common_prefix(first: &[u8], second: &[u8]) -> usize {
+    first.iter().zip(second.iter()).take_while(|&(a, b)| a == b).count()
+}
+
+fn nibbles(key: &[u8]) -> Vec<u8> {
+    key.iter().flat_map(|&byte| vec![byte >> 4, byte & 0xf]).collect()
+}
+
+// Nibbles packed two to a byte behind a flag nibble: 2 for leaves,
+//  plus 1 for an odd count, in which case the first nibble shares
+//  the flag's byte
+fn hex_prefix(nibbles: &[u8], is_leaf: bool) -> Vec<u8> {
+    let flag = if is_leaf { 2 } else { 0 };
+    let (mut out, rest) = if nibbles.len() % 2 == 1 {
+        (vec![(flag + 1) << 4 | nibbles[0]], &nibbles[1..])
+    } else {
+        (vec![flag << 4], nibbles)
+    };
+    for pair in rest.chunks(2) {
+        out.push(pair[0] << 4 | pair[1]);
+    }
+    out
+}
+
+fn from_hex_prefix(bytes: &[u8]) -> Option<(Vec<u8>, bool)> {
+    let (&first, rest) = bytes.split_first()?;
+    let flag = first >> 4;
+    if flag > 3 || (flag & 1 == 0 && first & 0xf != 0) {
+        return None
+    }
+    let mut path = if flag & 1 == 1 { vec![first & 0xf] } else { vec![] };
+    path.extend(nibbles(rest));
+    Some((path, flag & 2 == 2))
+}
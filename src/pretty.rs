@@ -0,0 +1,140 @@
+use std::hash::Hash;
+use std::fmt;
+
+use hash::BuildMerkleHasher;
+use tree::MerkleTree;
+use node::{Node, Child};
+use hex::to_hex;
+
+
+// Tree drawing for humans, e.g.
+//
+//  3f2a91c0 [3 leaves]
+//  ├── 8d01e5a2 [2 leaves, final]
+//  │   ├── #0 5b7e0c13
+//  │   └── #1 c4419f7d
+//  └── 0e6b2d88 [1 leaf]
+//      ├── #2 a90c6e21
+//      └── (dup a90c6e21)
+//
+// Hashes are cut to hash_length hex characters. Nodes deeper than
+//  max_depth, and nodes past the first max_width of their level, are
+//  folded: each prints one `… N leaves` line instead of its subtree.
+//  Only children of shown nodes get a line, so printing a huge tree
+//  stays cheap.
+//
+// MerkleTree's Debug uses the default options, pretty() takes custom ones.
+
+#[derive(Clone, Debug)]
+pub struct PrettyOptions {
+    pub hash_length: usize,
+    pub max_depth: usize,
+    pub max_width: usize
+}
+
+impl Default for PrettyOptions {
+    fn default() -> PrettyOptions {
+        PrettyOptions {
+            hash_length: 8,
+            max_depth: 6,
+            max_width: 16
+        }
+    }
+}
+
+pub struct Pretty<'a, V, S>
+where
+    V: Hash + Clone + 'a,
+    S: BuildMerkleHasher + 'a
+{
+    tree: &'a MerkleTree<V, S>,
+    options: PrettyOptions
+}
+
+pub fn pretty<'a, V, S>(tree: &'a MerkleTree<V, S>, options: PrettyOptions) -> Pretty<'a, V, S>
+where
+    V: Hash + Clone,
+    S: BuildMerkleHasher
+{
+    Pretty {
+        tree: tree,
+        options: options
+    }
+}
+
+impl<'a, V, S> fmt::Display for Pretty<'a, V, S>
+where
+    V: Hash + Clone,
+    S: BuildMerkleHasher
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.tree.root() {
+            &Some(ref root) => {
+                let mut shown = vec![0; self.options.max_depth + 1];
+                self.write_node(f, root, 0, 0, "", "", &mut shown)
+            },
+            &None => writeln!(f, "(empty)")
+        }
+    }
+}
+
+impl<'a, V, S> Pretty<'a, V, S>
+where
+    V: Hash + Clone,
+    S: BuildMerkleHasher
+{
+    // `lead` goes before this node's line, `indent` before its children's
+    fn write_node(
+        &self,
+        f: &mut fmt::Formatter,
+        node: &Child<V, S>,
+        depth: usize,
+        first_leaf: usize,
+        lead: &str,
+        indent: &str,
+        shown: &mut Vec<usize>) -> fmt::Result
+    {
+        if depth > self.options.max_depth || shown[depth] >= self.options.max_width {
+            return writeln!(f, "{}… {}", lead, leaves(node.leaf_count()))
+        }
+        shown[depth] += 1;
+
+        match **node {
+            Node::Leaf(_) => return writeln!(f, "{}#{} {}", lead, first_leaf, self.short_hex(node.hash_ref())),
+            Node::Branch(_) => {
+                let finality = if node.is_final() { ", final" } else { "" };
+                writeln!(f, "{}{} [{}{}]", lead, self.short_hex(node.hash_ref()), leaves(node.leaf_count()), finality)?;
+            }
+        }
+
+        let first = format!("{}├── ", indent);
+        let first_indent = format!("{}│   ", indent);
+        let last = format!("{}└── ", indent);
+        let last_indent = format!("{}    ", indent);
+        if let &Some(ref left) = node.left() {
+            self.write_node(f, left, depth + 1, first_leaf, &first, &first_indent, shown)?;
+            match node.right() {
+                &Some(ref right) => {
+                    let right_leaf = first_leaf + (1 << (node.height() - 1));
+                    self.write_node(f, right, depth + 1, right_leaf, &last, &last_indent, shown)?;
+                },
+                &None => writeln!(f, "{}(dup {})", last, self.short_hex(left.hash_ref()))?
+            }
+        }
+        Ok(())
+    }
+
+    fn short_hex(&self, bytes: &[u8]) -> String {
+        let mut hex = to_hex(bytes);
+        hex.truncate(self.options.hash_length);
+        hex
+    }
+}
+
+fn leaves(count: usize) -> String {
+    if count == 1 {
+        "1 leaf".to_string()
+    } else {
+        format!("{} leaves", count)
+    }
+}
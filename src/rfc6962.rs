@@ -0,0 +1,59 @@
+use std::hash::{Hash, Hasher};
+
+use hash::BuildMerkleHasher;
+use sha256::Sha256;
+use sync::Record;
+
+
+// Certificate Transparency (RFC 6962) hashing over SHA-256.
+//
+// A leaf is SHA-256(0x00 || entry) and a branch SHA-256(0x01 || left
+//  || right), the prefixes keep a leaf from passing for a branch.
+//  Entry wraps the entry bytes so that Hash feeds them in raw, without
+//  the length prefix Vec<u8> puts in front.
+//
+// The tree shape is this crate's: a node without a sibling is paired
+//  with itself, where RFC 6962 moves it up unchanged. Roots and proofs
+//  are RFC 6962's for sizes that are powers of two only; proofs for
+//  other sizes are checked with verify_at(), which knows the shape.
+
+#[derive(Clone, Default)]
+pub struct Rfc6962;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry(pub Vec<u8>);
+
+impl BuildMerkleHasher for Rfc6962 {
+    type Hasher = Sha256;
+
+    // Leaves are the only thing hashed through the hasher
+    fn build_hasher(&self) -> Sha256 {
+        let mut hasher = Sha256::new();
+        hasher.update(&[0x00]);
+        hasher
+    }
+
+    fn hash_branch(&self, left: &[u8], right: &[u8]) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(&[0x01]);
+        hasher.update(left);
+        hasher.update(right);
+        hasher.finalize()
+    }
+}
+
+impl Hash for Entry {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write(&self.0)
+    }
+}
+
+impl Record for Entry {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.0.clone()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Entry> {
+        Some(Entry(bytes.to_vec()))
+    }
+}
@@ -0,0 +1,133 @@
+use codec::{DecodeError, Reader};
+
+
+// Recursive Length Prefix, Ethereum's serialization
+//  (yellow paper, appendix B).
+//
+// An item is a byte string or a list of items:
+//
+//   single byte below 0x80      the byte itself
+//   bytes, up to 55             0x80 + length, then the bytes
+//   bytes, longer               0xb7 + length of the length, the
+//                                length (big-endian), then the bytes
+//   lists                       the same from 0xc0 and 0xf7, over the
+//                                concatenated encodings of the items
+//
+// decode() only takes the shortest encoding of every item, so
+//  re-encoding gives back the input, which hashes rely on.
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Rlp<'a> {
+    Bytes(&'a [u8]),
+    List(Vec<Rlp<'a>>)
+}
+
+impl<'a> Rlp<'a> {
+    pub fn as_bytes(&self) -> Option<&'a [u8]> {
+        match self {
+            &Rlp::Bytes(bytes) => Some(bytes),
+            &Rlp::List(_) => None
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[Rlp<'a>]> {
+        match self {
+            &Rlp::Bytes(_) => None,
+            &Rlp::List(ref items) => Some(items)
+        }
+    }
+}
+
+pub fn encode_bytes(bytes: &[u8]) -> Vec<u8> {
+    if bytes.len() == 1 && bytes[0] < 0x80 {
+        return bytes.to_vec()
+    }
+    let mut out = header(bytes.len(), 0x80);
+    out.extend_from_slice(bytes);
+    out
+}
+
+// List of items that are already encoded
+pub fn encode_list(items: &[Vec<u8>]) -> Vec<u8> {
+    let payload = items.concat();
+    let mut out = header(payload.len(), 0xc0);
+    out.extend_from_slice(&payload);
+    out
+}
+
+pub fn encode(item: &Rlp) -> Vec<u8> {
+    match item {
+        &Rlp::Bytes(bytes) => encode_bytes(bytes),
+        &Rlp::List(ref items) => encode_list(&items.iter().map(encode).collect::<Vec<_>>())
+    }
+}
+
+// Decodes a single item that takes up all of `bytes`
+pub fn decode<'a>(bytes: &'a [u8]) -> Result<Rlp<'a>, DecodeError> {
+    let mut reader = Reader { bytes: bytes };
+    let item = decode_item(&mut reader)?;
+    if !reader.bytes.is_empty() {
+        return Err(DecodeError::TrailingBytes)
+    }
+    Ok(item)
+}
+
+fn header(len: usize, offset: u8) -> Vec<u8> {
+    if len < 56 {
+        return vec![offset + len as u8]
+    }
+    let len = (len as u64).to_be_bytes();
+    let skipped = len.iter().take_while(|&&byte| byte == 0).count();
+    let mut out = vec![offset + 55 + (len.len() - skipped) as u8];
+    out.extend_from_slice(&len[skipped..]);
+    out
+}
+
+fn decode_item<'a>(reader: &mut Reader<'a>) -> Result<Rlp<'a>, DecodeError> {
+    let first = reader.take(1)?;
+    let prefix = first[0];
+    match prefix {
+        0x00..=0x7f => Ok(Rlp::Bytes(first)),
+        0x80..=0xb7 => {
+            let bytes = reader.take((prefix - 0x80) as usize)?;
+            if bytes.len() == 1 && bytes[0] < 0x80 {
+                return Err(DecodeError::NonCanonical)
+            }
+            Ok(Rlp::Bytes(bytes))
+        },
+        0xb8..=0xbf => {
+            let len = long_length(reader, (prefix - 0xb7) as usize)?;
+            Ok(Rlp::Bytes(reader.take(len)?))
+        },
+        0xc0..=0xf7 => decode_list(reader.take((prefix - 0xc0) as usize)?),
+        _ => {
+            let len = long_length(reader, (prefix - 0xf7) as usize)?;
+            decode_list(reader.take(len)?)
+        }
+    }
+}
+
+fn decode_list<'a>(payload: &'a [u8]) -> Result<Rlp<'a>, DecodeError> {
+    let mut reader = Reader { bytes: payload };
+    let mut items = vec![];
+    while !reader.bytes.is_empty() {
+        items.push(decode_item(&mut reader)?);
+    }
+    Ok(Rlp::List(items))
+}
+
+// Length of a long item, which must need the long form
+fn long_length(reader: &mut Reader, size: usize) -> Result<usize, DecodeError> {
+    let bytes = reader.take(size)?;
+    if bytes[0] == 0 {
+        return Err(DecodeError::NonCanonical)
+    }
+    let len = bytes.iter().fold(0u64, |len, &byte| len << 8 | byte as u64);
+    if len < 56 {
+        return Err(DecodeError::NonCanonical)
+    }
+    if len > reader.bytes.len() as u64 {
+        return Err(DecodeError::UnexpectedEnd)
+    }
+    Ok(len as usize)
+}
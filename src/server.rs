@@ -0,0 +1,328 @@
+use std::cmp::min;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use hash::Digest;
+use tree::Proof;
+use log::TransparencyLog;
+use rfc6962::{Rfc6962, Entry};
+use sth::Signer;
+use hex::{to_hex, from_hex};
+
+
+// Plain HTTP front end for a TransparencyLog of byte entries,
+//  with RFC 6962-style endpoints under /ct/v1/:
+//
+//  POST add-entry              {"entry": hex}  -> {"leaf_index"}
+//  GET  get-sth                                -> {"tree_size", "timestamp",
+//                                                  "root_hash", "tree_head_signature"}
+//  GET  get-sth-consistency    ?first&second   -> {"consistency": [hex]}
+//  GET  get-proof-by-hash      ?hash&tree_size -> {"leaf_index", "audit_path": [hex]}
+//  GET  get-entries            ?start&end      -> {"entries": [{"leaf_input": hex}]}
+//
+// Bytes are hex rather than base64, and the audit path has no
+//  directions: they follow from leaf_index and tree_size (see verify_at()).
+//  get-entries' end is inclusive, as in RFC 6962, and like RFC 6962
+//  allows the log returns at most MAX_ENTRIES entries from start.
+//
+// Hashes are RFC 6962's over SHA-256 (see rfc6962.rs): a leaf is
+//  SHA-256(0x00 || entry), which is the hash get-proof-by-hash takes,
+//  and a branch SHA-256(0x01 || left || right). A node without
+//  a sibling is paired with itself rather than moved up.
+//
+// Entries are added to the log straight away but only show up once
+//  a head covers them. Heads are published before handling a request
+//  when publish_interval has passed since the last one; the log is
+//  then saved to the state file, if there is one, by writing a new
+//  file and renaming it over the old one.
+//
+// Connections are handled one at a time and closed after the response,
+//  this is meant for localhost, not for the open internet.
+
+pub type EntryLog<G> = TransparencyLog<Entry, Rfc6962, G>;
+
+pub struct LogServer<G>
+where
+    G: Signer
+{
+    log: EntryLog<G>,
+    publish_interval: Duration,
+    last_publish: Option<Instant>,
+    state: Option<PathBuf>
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub body: String
+}
+
+// Largest request body accepted
+const MAX_BODY: usize = 1 << 20;
+
+// Longest request or header line and most headers accepted
+const MAX_LINE: u64 = 8 << 10;
+const MAX_HEADERS: usize = 100;
+
+// Most entries returned by one get-entries
+const MAX_ENTRIES: usize = 1000;
+
+// How long a client may take to send its request or read the response
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+impl<G> LogServer<G>
+where
+    G: Signer
+{
+    pub fn new(log: EntryLog<G>, publish_interval: Duration) -> LogServer<G> {
+        LogServer {
+            log: log,
+            publish_interval: publish_interval,
+            last_publish: None,
+            state: None
+        }
+    }
+
+    // Saves the log to the given file after every published head
+    pub fn with_state(mut self, path: PathBuf) -> LogServer<G> {
+        self.state = Some(path);
+        self
+    }
+
+    pub fn log(&self) -> &EntryLog<G> {
+        &self.log
+    }
+
+    pub fn serve(&mut self, listener: TcpListener) -> io::Result<()> {
+        for stream in listener.incoming() {
+            // A broken connection only concerns its client
+            let _ = self.handle_connection(stream?);
+        }
+        Ok(())
+    }
+
+    pub fn handle_connection(&mut self, stream: TcpStream) -> io::Result<()> {
+        // Connections are handled one at a time, a stalled client
+        //  mustn't hold up the others
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let response = match read_request(&mut reader) {
+            Ok((method, target, body)) => self.handle(&method, &target, &body),
+            Err(err) => error(400, &err.to_string())
+        };
+        write_response(stream, &response)
+    }
+
+    // Answers a single request, `target` is the path with the query
+    pub fn handle(&mut self, method: &str, target: &str, body: &[u8]) -> Response {
+        self.publish_if_due();
+        let (path, query) = match target.find('?') {
+            Some(split) => (&target[..split], &target[split + 1..]),
+            None => (target, "")
+        };
+        let query = Query(query);
+        let result = match (method, path) {
+            ("POST", "/ct/v1/add-entry") => self.add_entry(body),
+            ("GET", "/ct/v1/get-sth") => self.get_sth(),
+            ("GET", "/ct/v1/get-sth-consistency") => self.get_sth_consistency(&query),
+            ("GET", "/ct/v1/get-proof-by-hash") => self.get_proof_by_hash(&query),
+            ("GET", "/ct/v1/get-entries") => self.get_entries(&query),
+            _ => return error(404, "not found")
+        };
+        match result {
+            Ok(body) => Response { status: 200, body: body },
+            Err(message) => error(400, &message)
+        }
+    }
+
+    fn publish_if_due(&mut self) {
+        let due = match self.last_publish {
+            Some(last) => last.elapsed() >= self.publish_interval,
+            None => true
+        };
+        if !due || self.log.pending_count() == 0 {
+            return
+        }
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|since| since.as_millis() as u64)
+            .unwrap_or(0);
+        self.log.publish(timestamp);
+        self.last_publish = Some(Instant::now());
+        if let Some(ref path) = self.state {
+            // The log stays usable in memory if saving fails
+            if let Err(err) = save_state(&self.log, path) {
+                eprintln!("could not save {}: {}", path.display(), err);
+            }
+        }
+    }
+
+    fn add_entry(&mut self, body: &[u8]) -> Result<String, String> {
+        let body = String::from_utf8_lossy(body);
+        let entry = json_string(&body, "entry").ok_or("expected {\"entry\": hex}")?;
+        let entry = from_hex(&entry).map_err(|err| err.to_string())?;
+        let index = self.log.append(Entry(entry));
+        Ok(format!("{{\"leaf_index\":{}}}", index))
+    }
+
+    fn get_sth(&self) -> Result<String, String> {
+        let head = self.log.latest_head().ok_or("no tree head yet")?;
+        Ok(format!("{{\"tree_size\":{},\"timestamp\":{},\"root_hash\":\"{}\",\"tree_head_signature\":\"{}\"}}",
+            head.tree_size, head.timestamp, to_hex(head.root_hash.as_ref()), to_hex(&head.signature)))
+    }
+
+    fn get_sth_consistency(&self, query: &Query) -> Result<String, String> {
+        let first = query.number("first")?;
+        let second = query.number("second")?;
+        let proof = self.log.get_consistency(first, second)
+            .ok_or(format!("no consistency proof from {} to {}", first, second))?;
+        Ok(format!("{{\"consistency\":{}}}", hex_list(proof.iter().map(|hash| hash.as_ref()))))
+    }
+
+    fn get_proof_by_hash(&self, query: &Query) -> Result<String, String> {
+        let hash = query.get("hash").ok_or("missing hash")?;
+        let hash = from_hex(hash).ok()
+            .and_then(|bytes| <[u8; 32]>::from_bytes(&bytes))
+            .ok_or("invalid hash")?;
+        let tree_size = query.number("tree_size")?;
+        let (index, proof) = self.log.get_proof_by_hash(&hash, tree_size)
+            .ok_or("no entry with that hash in the tree of that size")?;
+        let path = proof.iter().map(|piece| match piece {
+            &Proof::Left(ref hash) => hash.as_ref(),
+            &Proof::Right(ref hash) => hash.as_ref()
+        });
+        Ok(format!("{{\"leaf_index\":{},\"audit_path\":{}}}", index, hex_list(path)))
+    }
+
+    fn get_entries(&self, query: &Query) -> Result<String, String> {
+        let start = query.number("start")?;
+        let end = query.number("end")?;
+        if end < start {
+            return Err("end is before start".to_string())
+        }
+        let end = min(end, start.saturating_add(MAX_ENTRIES - 1));
+        let entries: Vec<String> = self.log.get_entries(start, end.saturating_add(1)).iter()
+            .map(|entry| format!("{{\"leaf_input\":\"{}\"}}", to_hex(&entry.0)))
+            .collect();
+        Ok(format!("{{\"entries\":[{}]}}", entries.join(",")))
+    }
+}
+
+struct Query<'a>(&'a str);
+
+impl<'a> Query<'a> {
+    fn get(&self, key: &str) -> Option<&'a str> {
+        self.0.split('&')
+            .filter_map(|pair| {
+                let mut parts = pair.splitn(2, '=');
+                match (parts.next(), parts.next()) {
+                    (Some(name), Some(value)) if name == key => Some(value),
+                    _ => None
+                }
+            })
+            .next()
+    }
+
+    fn number(&self, key: &str) -> Result<usize, String> {
+        self.get(key)
+            .and_then(|value| value.parse().ok())
+            .ok_or(format!("missing or invalid {}", key))
+    }
+}
+
+// Writes the log next to the state file and renames it over it,
+//  so a crash while saving leaves the previous state intact
+fn save_state<G>(log: &EntryLog<G>, path: &Path) -> io::Result<()>
+where
+    G: Signer
+{
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let mut file = File::create(&temp)?;
+    log.save(&mut file)?;
+    file.sync_all()?;
+    fs::rename(&temp, path)
+}
+
+fn error(status: u16, message: &str) -> Response {
+    Response {
+        status: status,
+        body: format!("{{\"error\":\"{}\"}}", message.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+fn hex_list<'a, I: Iterator<Item = &'a [u8]>>(hashes: I) -> String {
+    let hashes: Vec<String> = hashes
+        .map(|hash| format!("\"{}\"", to_hex(hash)))
+        .collect();
+    format!("[{}]", hashes.join(","))
+}
+
+// Value of a string member of a flat JSON object, escapes are not
+//  supported as hex never needs them
+fn json_string(json: &str, key: &str) -> Option<String> {
+    let name = format!("\"{}\"", key);
+    let rest = json[json.find(&name)? + name.len()..].trim_start();
+    let rest = rest.strip_prefix(':')?.trim_start().strip_prefix('"')?;
+    let end = rest.find('"')?;
+    Some(rest[..end].to_string())
+}
+
+pub(crate) fn read_request<R: BufRead>(reader: &mut R) -> io::Result<(String, String, Vec<u8>)> {
+    let line = read_line(reader)?;
+    let mut words = line.split_whitespace();
+    let (method, target) = match (words.next(), words.next()) {
+        (Some(method), Some(target)) => (method.to_string(), target.to_string()),
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid request line"))
+    };
+
+    let mut length = 0;
+    let mut headers = 0;
+    loop {
+        let header = read_line(reader)?;
+        if header.trim().is_empty() {
+            break
+        }
+        headers += 1;
+        if headers > MAX_HEADERS {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "too many headers"))
+        }
+        let mut parts = header.splitn(2, ':');
+        if let (Some(name), Some(value)) = (parts.next(), parts.next()) {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse()
+                    .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid content length"))?;
+            }
+        }
+    }
+    if length > MAX_BODY {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "body too large"))
+    }
+    let mut body = vec![0u8; length];
+    reader.read_exact(&mut body)?;
+    Ok((method, target, body))
+}
+
+// Line up to MAX_LINE long, empty at the end of the input
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<String> {
+    let mut line = String::new();
+    reader.by_ref().take(MAX_LINE).read_line(&mut line)?;
+    if line.len() as u64 == MAX_LINE && !line.ends_with('\n') {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "line too long"))
+    }
+    Ok(line)
+}
+
+fn write_response<W: Write>(mut out: W, response: &Response) -> io::Result<()> {
+    let reason = match response.status {
+        200 => "OK",
+        400 => "Bad Request",
+        _ => "Not Found"
+    };
+    write!(out, "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status, reason, response.body.len(), response.body)?;
+    out.flush()
+}
@@ -0,0 +1,161 @@
+use std::hash::Hash;
+
+use hash::{BuildMerkleHasher, MerkleHasher, Digest};
+use tree::{MerkleTree, Proof, ProofError, verify_at};
+use history::verify_consistency;
+use codec::{DecodeError, Reader, put_u64, put_bytes};
+
+
+// Signed tree heads for transparency logs.
+//
+// A tree head is the size, root hash and a timestamp of a tree.
+//  Signers sign its canonical serialization (signed_data()):
+//
+//   version (1 byte, 0)
+//   tree size (u64, big-endian)
+//   timestamp (u64, big-endian, whatever unit the log uses)
+//   root hash (u64 length, big-endian, then the bytes)
+//
+// encode() appends the signature the same way as the root hash.
+//
+// Signing is behind the Signer and Verifier traits, the crate doesn't
+//  pick a signature scheme. hmac.rs has a shared-key one, which the
+//  local log server uses.
+//
+// check_inclusion() and check_consistency() check the signatures
+//  before the proofs, so a proof is never taken against a head that
+//  wasn't signed. See history.rs for proofs against earlier sizes.
+
+const VERSION: u8 = 0;
+
+pub trait Signer {
+    fn sign(&self, data: &[u8]) -> Vec<u8>;
+}
+
+pub trait Verifier {
+    fn verify(&self, data: &[u8], signature: &[u8]) -> bool;
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SignedTreeHead<H> {
+    pub tree_size: u64,
+    pub timestamp: u64,
+    pub root_hash: H,
+    pub signature: Vec<u8>
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SthError {
+    BadSignature,
+    Proof(ProofError)
+}
+
+impl<H> SignedTreeHead<H>
+where
+    H: Digest
+{
+    // Head of the tree as it is now, None for an empty tree
+    pub fn sign<V, S, G>(tree: &MerkleTree<V, S>, timestamp: u64, signer: &G) -> Option<SignedTreeHead<H>>
+    where
+        V: Hash + Clone,
+        S: BuildMerkleHasher,
+        S::Hasher: MerkleHasher<Output = H>,
+        G: Signer
+    {
+        let root_hash = tree.root_hash()?;
+        let tree_size = tree.leaf_count().unwrap_or(0) as u64;
+        let signature = signer.sign(&signed_data(tree_size, timestamp, root_hash.as_ref()));
+        Some(SignedTreeHead {
+            tree_size: tree_size,
+            timestamp: timestamp,
+            root_hash: root_hash,
+            signature: signature
+        })
+    }
+
+    pub fn signed_data(&self) -> Vec<u8> {
+        signed_data(self.tree_size, self.timestamp, self.root_hash.as_ref())
+    }
+
+    pub fn verify_signature<G: Verifier>(&self, verifier: &G) -> bool {
+        verifier.verify(&self.signed_data(), &self.signature)
+    }
+
+    // Checks that the leaf with the given hash sits at `index`
+    //  in the tree this head was signed for
+    pub fn check_inclusion<S, G>(
+        &self,
+        verifier: &G,
+        leaf_hash: &H,
+        index: usize,
+        proof: &[Proof<H>],
+        hasher_builder: &S) -> Result<(), SthError>
+    where
+        S: BuildMerkleHasher,
+        S::Hasher: MerkleHasher<Output = H>,
+        G: Verifier
+    {
+        if !self.verify_signature(verifier) {
+            return Err(SthError::BadSignature)
+        }
+        verify_at(leaf_hash, proof, index, self.tree_size as usize, &self.root_hash, hasher_builder)
+            .map_err(SthError::Proof)
+    }
+
+    // Checks that `new` is `old` with leaves appended
+    pub fn check_consistency<S, G>(
+        old: &SignedTreeHead<H>,
+        new: &SignedTreeHead<H>,
+        verifier: &G,
+        proof: &[H],
+        hasher_builder: &S) -> Result<(), SthError>
+    where
+        S: BuildMerkleHasher,
+        S::Hasher: MerkleHasher<Output = H>,
+        G: Verifier
+    {
+        if !old.verify_signature(verifier) || !new.verify_signature(verifier) {
+            return Err(SthError::BadSignature)
+        }
+        verify_consistency(
+            old.tree_size as usize, &old.root_hash,
+            new.tree_size as usize, &new.root_hash,
+            proof, hasher_builder)
+            .map_err(SthError::Proof)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = self.signed_data();
+        put_bytes(&mut out, &self.signature);
+        out
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<SignedTreeHead<H>, DecodeError> {
+        let mut reader = Reader { bytes: bytes };
+        let version = reader.byte()?;
+        if version != VERSION {
+            return Err(DecodeError::UnknownVersion(version))
+        }
+        let tree_size = reader.u64()?;
+        let timestamp = reader.u64()?;
+        let root_hash = H::from_bytes(&reader.bytes()?).ok_or(DecodeError::BadHash)?;
+        let signature = reader.bytes()?;
+        if !reader.bytes.is_empty() {
+            return Err(DecodeError::TrailingBytes)
+        }
+        Ok(SignedTreeHead {
+            tree_size: tree_size,
+            timestamp: timestamp,
+            root_hash: root_hash,
+            signature: signature
+        })
+    }
+}
+
+fn signed_data(tree_size: u64, timestamp: u64, root_hash: &[u8]) -> Vec<u8> {
+    let mut out = vec![VERSION];
+    put_u64(&mut out, tree_size);
+    put_u64(&mut out, timestamp);
+    put_bytes(&mut out, root_hash);
+    out
+}
@@ -0,0 +1,487 @@
+use std::cmp::min;
+use std::hash::Hash;
+use std::mem;
+use std::ops::Range;
+use std::collections::BTreeMap;
+
+use hash::{BuildMerkleHasher, Digest};
+use node::{Node, HashValue};
+use tree::{MerkleTree, NoValue, InsertError, height_for};
+use diff::push_range;
+use codec::{DecodeError, Reader, put_u64, put_bytes};
+use hex::ct_eq;
+
+
+// Anti-entropy sync between two replicas.
+//
+// A Reconciler brings a local tree in line with a remote one
+//  by talking to respond() running next to the remote tree:
+//
+//   GetRoot                      ->
+//                                <-  Root { leaf_count, hash }
+//   GetHashes { level, indices } ->
+//                                <-  Hashes { level, nodes }
+//   ... one round per level, only for mismatched subtrees ...
+//   GetLeaves { ranges }         ->
+//                                <-  Leaves { leaves }
+//   ... until every missing leaf is in, respond() sends at most
+//       MAX_LEAVES_PER_RESPONSE at a time ...
+//
+// Levels and indices address nodes the same way as MerkleTree::node_at(),
+//  so only hashes of subtrees that actually differ cross the wire.
+// Subtrees the local replica has no leaves for are fetched as leaf
+//  ranges straight away instead of being descended into.
+// A remote tree taller than the local one is compared from the
+//  level of the local root down.
+//
+// Responses are checked against the requests they answer, and trees
+//  over MAX_LEAVES leaves are refused, so a remote can't make the
+//  Reconciler overflow or allocate more than the sync needs.
+//
+// Messages are plain data with encode()/decode(), the transport is
+//  up to the user. Leaf values travel as bytes produced by Record,
+//  leaves inserted as hashes travel as their hash.
+
+
+pub trait Record: Sized {
+    fn to_bytes(&self) -> Vec<u8>;
+    fn from_bytes(bytes: &[u8]) -> Option<Self>;
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct NodeHash {
+    pub index: u64,
+    pub leaf_count: u64,
+    pub hash: Vec<u8>
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LeafData {
+    Value(Vec<u8>),
+    Hash(Vec<u8>)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    GetRoot,
+    Root { leaf_count: u64, hash: Vec<u8> },
+    GetHashes { level: u64, indices: Vec<u64> },
+    Hashes { level: u64, nodes: Vec<NodeHash> },
+    GetLeaves { ranges: Vec<Range<u64>> },
+    Leaves { leaves: Vec<(u64, LeafData)> }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SyncError {
+    UnexpectedMessage,
+    MissingLeaf(usize),
+    BadLeaf(usize),
+    // The local duplicate policy rejected a fetched copy of this leaf
+    DuplicateLeaf(usize),
+    // The remote tree is bigger than MAX_LEAVES
+    TooManyLeaves(u64),
+    // Hashes for a level that wasn't asked for
+    UnexpectedLevel(u64),
+    // A node that can't be at its position in the remote tree
+    BadNode(u64),
+    RootMismatch
+}
+
+// Largest remote tree a Reconciler accepts
+pub const MAX_LEAVES: u64 = 1 << 32;
+
+// Most leaves respond() sends at once, the Reconciler asks again
+//  for the rest
+pub const MAX_LEAVES_PER_RESPONSE: usize = 1 << 16;
+
+const GET_ROOT: u8 = 0;
+const ROOT: u8 = 1;
+const GET_HASHES: u8 = 2;
+const HASHES: u8 = 3;
+const GET_LEAVES: u8 = 4;
+const LEAVES: u8 = 5;
+
+const VALUE: u8 = 0;
+const HASH: u8 = 1;
+
+impl Message {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![];
+        match self {
+            &Message::GetRoot => out.push(GET_ROOT),
+            &Message::Root { leaf_count, ref hash } => {
+                out.push(ROOT);
+                put_u64(&mut out, leaf_count);
+                put_bytes(&mut out, hash);
+            },
+            &Message::GetHashes { level, ref indices } => {
+                out.push(GET_HASHES);
+                put_u64(&mut out, level);
+                put_u64(&mut out, indices.len() as u64);
+                for &index in indices {
+                    put_u64(&mut out, index);
+                }
+            },
+            &Message::Hashes { level, ref nodes } => {
+                out.push(HASHES);
+                put_u64(&mut out, level);
+                put_u64(&mut out, nodes.len() as u64);
+                for node in nodes {
+                    put_u64(&mut out, node.index);
+                    put_u64(&mut out, node.leaf_count);
+                    put_bytes(&mut out, &node.hash);
+                }
+            },
+            &Message::GetLeaves { ref ranges } => {
+                out.push(GET_LEAVES);
+                put_u64(&mut out, ranges.len() as u64);
+                for range in ranges {
+                    put_u64(&mut out, range.start);
+                    put_u64(&mut out, range.end);
+                }
+            },
+            &Message::Leaves { ref leaves } => {
+                out.push(LEAVES);
+                put_u64(&mut out, leaves.len() as u64);
+                for &(index, ref leaf) in leaves {
+                    put_u64(&mut out, index);
+                    match leaf {
+                        &LeafData::Value(ref value) => {
+                            out.push(VALUE);
+                            put_bytes(&mut out, value);
+                        },
+                        &LeafData::Hash(ref hash) => {
+                            out.push(HASH);
+                            put_bytes(&mut out, hash);
+                        }
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Message, DecodeError> {
+        let mut reader = Reader { bytes: bytes };
+        let message = match reader.byte()? {
+            GET_ROOT => Message::GetRoot,
+            ROOT => Message::Root {
+                leaf_count: reader.u64()?,
+                hash: reader.bytes()?
+            },
+            GET_HASHES => {
+                let level = reader.u64()?;
+                let mut indices = vec![];
+                for _ in 0..reader.u64()? {
+                    indices.push(reader.u64()?);
+                }
+                Message::GetHashes { level: level, indices: indices }
+            },
+            HASHES => {
+                let level = reader.u64()?;
+                let mut nodes = vec![];
+                for _ in 0..reader.u64()? {
+                    nodes.push(NodeHash {
+                        index: reader.u64()?,
+                        leaf_count: reader.u64()?,
+                        hash: reader.bytes()?
+                    });
+                }
+                Message::Hashes { level: level, nodes: nodes }
+            },
+            GET_LEAVES => {
+                let mut ranges = vec![];
+                for _ in 0..reader.u64()? {
+                    let start = reader.u64()?;
+                    ranges.push(start..reader.u64()?);
+                }
+                Message::GetLeaves { ranges: ranges }
+            },
+            LEAVES => {
+                let mut leaves = vec![];
+                for _ in 0..reader.u64()? {
+                    let index = reader.u64()?;
+                    let leaf = match reader.byte()? {
+                        VALUE => LeafData::Value(reader.bytes()?),
+                        HASH => LeafData::Hash(reader.bytes()?),
+                        tag => return Err(DecodeError::UnknownTag(tag))
+                    };
+                    leaves.push((index, leaf));
+                }
+                Message::Leaves { leaves: leaves }
+            },
+            tag => return Err(DecodeError::UnknownTag(tag))
+        };
+        if reader.bytes.is_empty() {
+            Ok(message)
+        } else {
+            Err(DecodeError::TrailingBytes)
+        }
+    }
+}
+
+// Answers a request from a remote Reconciler.
+// Returns None for messages that are not requests.
+pub fn respond<V, S>(tree: &MerkleTree<V, S>, request: &Message) -> Option<Message>
+where
+    V: Hash + Clone + Record,
+    S: BuildMerkleHasher
+{
+    match request {
+        &Message::GetRoot => {
+            let response = match tree.root() {
+                &Some(ref root) => Message::Root {
+                    leaf_count: root.leaf_count() as u64,
+                    hash: root.hash_ref().to_vec()
+                },
+                _ => Message::Root { leaf_count: 0, hash: vec![] }
+            };
+            Some(response)
+        },
+        &Message::GetHashes { level, ref indices } => {
+            let nodes = indices.iter()
+                .filter_map(|&index| {
+                    let node = tree.node_at(level as usize, index as usize)?;
+                    Some(NodeHash {
+                        index: index,
+                        leaf_count: node.leaf_count() as u64,
+                        hash: node.hash_ref().to_vec()
+                    })
+                })
+                .collect();
+            Some(Message::Hashes { level: level, nodes: nodes })
+        },
+        &Message::GetLeaves { ref ranges } => {
+            let leaf_count = tree.leaf_count().unwrap_or(0) as u64;
+            let mut leaves = vec![];
+            'ranges: for range in ranges {
+                for index in range.start..min(range.end, leaf_count) {
+                    if leaves.len() == MAX_LEAVES_PER_RESPONSE {
+                        break 'ranges
+                    }
+                    if let Some(node) = tree.node_at(0, index as usize) {
+                        let leaf = match node.value() {
+                            Some(value) => LeafData::Value(value.to_bytes()),
+                            None => LeafData::Hash(node.hash_ref().to_vec())
+                        };
+                        leaves.push((index, leaf));
+                    }
+                }
+            }
+            Some(Message::Leaves { leaves: leaves })
+        },
+        _ => None
+    }
+}
+
+pub struct Reconciler<'a, V, S>
+where
+    V: 'a + Hash + Clone + Record,
+    S: 'a + BuildMerkleHasher
+{
+    local: &'a mut MerkleTree<V, S>,
+    remote_count: usize,
+    remote_hash: Vec<u8>,
+    // Level of the hashes asked for last
+    requested_level: Option<usize>,
+    missing: Vec<Range<usize>>,
+    fetched: BTreeMap<usize, LeafData>
+}
+
+impl<'a, V, S> Reconciler<'a, V, S>
+where
+    V: Hash + Clone + Record,
+    S: BuildMerkleHasher
+{
+    pub fn new(local: &'a mut MerkleTree<V, S>) -> Reconciler<'a, V, S> {
+        Reconciler {
+            local: local,
+            remote_count: 0,
+            remote_hash: vec![],
+            requested_level: None,
+            missing: vec![],
+            fetched: BTreeMap::new()
+        }
+    }
+
+    pub fn start(&self) -> Message {
+        Message::GetRoot
+    }
+
+    // Feeds a response from the remote side.
+    // Returns the next request to send, or None once the local tree
+    //  has been brought in line with the remote one.
+    pub fn receive(&mut self, message: Message) -> Result<Option<Message>, SyncError> {
+        match message {
+            Message::Root { leaf_count, hash } => {
+                if leaf_count > MAX_LEAVES {
+                    return Err(SyncError::TooManyLeaves(leaf_count))
+                }
+                self.remote_count = leaf_count as usize;
+                self.remote_hash = hash.clone();
+                if self.remote_count == 0 {
+                    self.apply()?;
+                    return Ok(None)
+                }
+                let local_height = self.local.height();
+                if self.local.leaf_count().is_some() && height_for(self.remote_count) > local_height {
+                    // The whole local tree is under the remote node at
+                    //  (local_height, 0), everything right of it is new
+                    self.missing.push(1 << local_height..self.remote_count);
+                    self.requested_level = Some(local_height);
+                    return Ok(Some(Message::GetHashes { level: local_height as u64, indices: vec![0] }))
+                }
+                let root = NodeHash { index: 0, leaf_count: leaf_count, hash: hash };
+                self.compare(height_for(self.remote_count), vec![root])
+            },
+            Message::Hashes { level, nodes } => {
+                match self.requested_level.take() {
+                    Some(requested) if requested as u64 == level && requested <= self.local.height() => (),
+                    _ => return Err(SyncError::UnexpectedLevel(level))
+                }
+                self.compare(level as usize, nodes)
+            },
+            Message::Leaves { leaves } => {
+                let before = self.fetched.len();
+                for (index, leaf) in leaves {
+                    if index >= self.remote_count as u64 {
+                        return Err(SyncError::BadLeaf(index as usize))
+                    }
+                    self.fetched.insert(index as usize, leaf);
+                }
+                let ranges = self.unfetched();
+                // Without progress, apply() reports the first missing leaf
+                if ranges.is_empty() || self.fetched.len() == before {
+                    self.apply()?;
+                    return Ok(None)
+                }
+                Ok(Some(Message::GetLeaves { ranges: ranges }))
+            },
+            _ => Err(SyncError::UnexpectedMessage)
+        }
+    }
+
+    fn compare(&mut self, level: usize, nodes: Vec<NodeHash>) -> Result<Option<Message>, SyncError> {
+        let local_count = self.local.leaf_count().unwrap_or(0);
+        let mut mismatched = vec![];
+        for node in nodes {
+            // Levels are at most the local height and the remote tree
+            //  is bounded by MAX_LEAVES, so these shifts can't overflow
+            if node.index > (self.remote_count as u64 - 1) >> level
+                || node.leaf_count == 0 || node.leaf_count > 1 << level {
+                return Err(SyncError::BadNode(node.index))
+            }
+            let index = node.index as usize;
+            let start = index << level;
+            if node.leaf_count as usize > self.remote_count - start {
+                return Err(SyncError::BadNode(node.index))
+            }
+            match self.local.node_at(level, index) {
+                Some(ref local) if local.leaf_count() as u64 == node.leaf_count
+                    && ct_eq(local.hash_ref(), &node.hash) => (),
+                _ if level == 0 || start >= local_count => {
+                    self.missing.push(start..start + node.leaf_count as usize)
+                },
+                _ => mismatched.push(index)
+            }
+        }
+
+        if !mismatched.is_empty() {
+            let level = level - 1;
+            let indices = mismatched.into_iter()
+                .flat_map(|index| vec![2 * index, 2 * index + 1])
+                .filter(|&index| index << level < self.remote_count)
+                .map(|index| index as u64)
+                .collect();
+            self.requested_level = Some(level);
+            Ok(Some(Message::GetHashes { level: level as u64, indices: indices }))
+        } else if !self.missing.is_empty() {
+            Ok(Some(Message::GetLeaves { ranges: self.unfetched() }))
+        } else if local_count != self.remote_count {
+            self.apply()?;
+            Ok(None)
+        } else {
+            Ok(None)
+        }
+    }
+
+    // Missing leaf ranges not fetched yet, in order
+    fn unfetched(&self) -> Vec<Range<u64>> {
+        let mut missing = self.missing.clone();
+        missing.sort_by_key(|range| range.start);
+        let mut ranges = vec![];
+        for range in missing {
+            let mut start = range.start;
+            for (&index, _) in self.fetched.range(range.clone()) {
+                if start < index {
+                    push_range(&mut ranges, start..index);
+                }
+                start = index + 1;
+            }
+            if start < range.end {
+                push_range(&mut ranges, start..range.end);
+            }
+        }
+        ranges.into_iter()
+            .map(|range| range.start as u64..range.end as u64)
+            .collect()
+    }
+
+    // Rebuilds the local tree from local leaves patched with fetched ones,
+    //  keeping its duplicate policy and leaf index
+    fn apply(&mut self) -> Result<(), SyncError> {
+        let hasher_builder = self.local.hasher_builder();
+        let fetched = mem::take(&mut self.fetched);
+        // Every leaf comes from one side or the other, so this
+        //  doesn't take the remote's word for how many there are
+        let available = self.local.leaf_count().unwrap_or(0) + fetched.len();
+        let mut nodes = Vec::with_capacity(min(self.remote_count, available));
+        for index in 0..self.remote_count {
+            let node = match fetched.get(&index) {
+                Some(&LeafData::Value(ref bytes)) => {
+                    let value = V::from_bytes(bytes).ok_or(SyncError::BadLeaf(index))?;
+                    Box::new(Node::new_leaf(value, hasher_builder.clone()))
+                },
+                Some(&LeafData::Hash(ref hash)) => {
+                    let hash = HashValue::<S>::from_bytes(hash).ok_or(SyncError::BadLeaf(index))?;
+                    Box::new(Node::new_hash_leaf(hash, hasher_builder.clone()))
+                },
+                None => self.local.node_at(0, index).cloned().ok_or(SyncError::MissingLeaf(index))?
+            };
+            nodes.push(node);
+        }
+
+        let mut tree = self.local.empty_like();
+        tree.try_insert_leaves(nodes).map_err(|InsertError::Duplicate(index)| SyncError::DuplicateLeaf(index))?;
+        let matches = match tree.root() {
+            &Some(ref root) => ct_eq(root.hash_ref(), &self.remote_hash),
+            _ => self.remote_hash.is_empty()
+        };
+        if !matches {
+            return Err(SyncError::RootMismatch)
+        }
+        *self.local = tree;
+        Ok(())
+    }
+}
+
+// Trees of NoValue only ever hold hashes, which are sent as is
+impl Record for NoValue {
+    fn to_bytes(&self) -> Vec<u8> {
+        match *self {}
+    }
+
+    fn from_bytes(_bytes: &[u8]) -> Option<NoValue> {
+        None
+    }
+}
+
+impl Record for Vec<u8> {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.clone()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Vec<u8>> {
+        Some(bytes.to_vec())
+    }
+}
@@ -1,9 +1,12 @@
 use std::hash::{Hash, Hasher};
-use std::collections::VecDeque;
+use std::collections::{VecDeque, HashMap};
 use std::fmt;
+use std::thread;
 
 use hash::{BuildMerkleHasher, MerkleHasher};
-use node::{Node, Child};
+use node::{Node, Child, HashValue};
+use pretty::{pretty, PrettyOptions};
+use hex::ct_eq;
 
 
 // MerkleTree is the main user interface.
@@ -13,47 +16,199 @@ use node::{Node, Child};
 //  2) Reuse `final` nodes when reconstructing trees.
 //     This eliminates the need of rehashing and rebuilding
 //     nodes unnecessarly. (see build_tree())
+//     Final nodes are moved out of the old root rather than copied,
+//     so an append only rebuilds the right edge: O(log n). (see recycle())
 //
-// value_proof() and tree_proof() produce membership 
-//  and consistency proofs respectively, which is made possible
+// value_proof() produces membership proofs, which is made possible
 //  by the fact that leaves preserve insertion order (!!!).
+// index_proof() is the cheap one: it walks a single path down
+//  to a known position instead of searching the tree.
+// subtree_proof() does the same for any node, given its level and
+//  index; verify_subtree() checks the node sits exactly there.
+//  tree_proof() finds where another tree sits as one of our subtrees.
+//
+// Leaves can be inserted as precomputed hashes with insert_hash(),
+//  e.g. when the data already is a digest. HashTree is a tree
+//  that never holds values, only leaf hashes.
+//
+// verify() only checks that a path leads to the root, verify_at()
+//  also checks the path is the one a leaf at a given position
+//  in a tree of a given size would have.
+//
+// Inserting a leaf that is already there is allowed by default,
+//  set_duplicate_policy() can make insertion reject or skip it.
+//  Rejected leaves make the try_insert functions return an error
+//  and the insert ones panic.
+//
+// enable_leaf_index() keeps a hash -> positions map up to date on
+//  insertion, for looking leaves up by hash without a search.
+//
+// Hashes that may come from the outside are compared with ct_eq(),
+//  which doesn't give away how much of a hash matched.
+//
+// insert_items_parallel() hashes leaves and independent subtrees
+//  on several threads. Subtrees are split exactly where build_tree()
+//  would split them, so the result is identical to insert_items().
 
 
-pub enum Proof {
-    Left(Box<[u8]>),
-    Right(Box<[u8]>)    
+// Proof pieces hold sibling hashes of the hasher's output type,
+//  see HashValue.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Proof<H> {
+    Left(H),
+    Right(H)
 }
 
-pub fn prove<V, S>(target: &[u8], proof: &[Proof], tree: &MerkleTree<V, S>) -> bool
+pub fn prove<V, S>(target: &HashValue<S>, proof: &[Proof<HashValue<S>>], tree: &MerkleTree<V, S>) -> bool
 where 
     V: Hash + Clone,
     S: BuildMerkleHasher
 {
-    if let Some(root_hash) = tree.root_hash() {
-        let hasher_builder = tree.hasher_builder();
-        let mut current_hash: Box<[u8]> = Box::from(target);
-        for piece in proof {
-            match piece {
-                &Proof::Left(ref left_hash) => {
-                    let mut hasher = hasher_builder.build_hasher();
-                    (*left_hash).as_ref().hash(&mut hasher);
-                    current_hash.as_ref().hash(&mut hasher);
-                    current_hash = hasher.finish_full();
-                },
-                &Proof::Right(ref right_hash) => {
-                    let mut hasher = hasher_builder.build_hasher();
-                    current_hash.as_ref().hash(&mut hasher);                            
-                    (*right_hash).as_ref().hash(&mut hasher);
-                    current_hash = hasher.finish_full();
-                },
-            }
-        }
-        *current_hash == *root_hash
+    if let &Some(ref root) = tree.root() {
+        verify(target, proof, &root.hash_value(), &tree.hasher_builder)
     } else {
         false
     }
 }
 
+// Same as prove(), but against a known root hash instead of a tree
+pub fn verify<S>(
+    target: &HashValue<S>,
+    proof: &[Proof<HashValue<S>>],
+    root_hash: &HashValue<S>,
+    hasher_builder: &S) -> bool
+where
+    S: BuildMerkleHasher
+{
+    let mut current_hash = target.clone();
+    for piece in proof {
+        current_hash = match piece {
+            &Proof::Left(ref left_hash) => hash_pair(left_hash, &current_hash, hasher_builder),
+            &Proof::Right(ref right_hash) => hash_pair(&current_hash, right_hash, hasher_builder)
+        };
+    }
+    ct_eq(current_hash.as_ref(), root_hash.as_ref())
+}
+
+// Why a proof for a given position failed, see verify_at()
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProofError {
+    IndexOutOfRange { index: usize, leaf_count: usize },
+    WrongLength { expected: usize, found: usize },
+    // Sibling on the wrong side at this level (0 is the leaves)
+    WrongDirection(usize),
+    // Node has no sibling at this level, so the proof must repeat it
+    BadLonelySibling(usize),
+    RootMismatch
+}
+
+// Strict version of verify() for a proof of the leaf at `index`
+//  in a tree of `leaf_count` leaves.
+// The path must have exactly the tree's height, go left or right
+//  as the index says, and repeat the current hash wherever a node
+//  has no sibling, so every leaf has exactly one valid proof.
+pub fn verify_at<S>(
+    target: &HashValue<S>,
+    proof: &[Proof<HashValue<S>>],
+    index: usize,
+    leaf_count: usize,
+    root_hash: &HashValue<S>,
+    hasher_builder: &S) -> Result<(), ProofError>
+where
+    S: BuildMerkleHasher
+{
+    verify_subtree(target, 0, index, proof, leaf_count, root_hash, hasher_builder)
+}
+
+// Same as verify_at() for the node at (level, index) with the given
+//  hash, as given by subtree_proof(). Levels in errors count from
+//  the leaves, not from the node.
+pub fn verify_subtree<S>(
+    target: &HashValue<S>,
+    level: usize,
+    index: usize,
+    proof: &[Proof<HashValue<S>>],
+    leaf_count: usize,
+    root_hash: &HashValue<S>,
+    hasher_builder: &S) -> Result<(), ProofError>
+where
+    S: BuildMerkleHasher
+{
+    let height = height_for(leaf_count);
+    // Compare against the last node of the level rather than shifting
+    //  the index up, which would drop its high bits
+    if leaf_count == 0 || level > height || index > (leaf_count - 1) >> level {
+        return Err(ProofError::IndexOutOfRange { index: index, leaf_count: leaf_count })
+    }
+    if proof.len() != height - level {
+        return Err(ProofError::WrongLength { expected: height - level, found: proof.len() })
+    }
+    let mut current_hash = target.clone();
+    for (step, piece) in proof.iter().enumerate() {
+        let position = index >> step;
+        let level = level + step;
+        current_hash = match piece {
+            &Proof::Left(ref left_hash) if position & 1 == 1 => hash_pair(left_hash, &current_hash, hasher_builder),
+            &Proof::Right(ref right_hash) if position & 1 == 0 => {
+                // The last node of a level is the only one that can be lonely
+                let lonely = position == (leaf_count - 1) >> level;
+                if lonely && !ct_eq(right_hash.as_ref(), current_hash.as_ref()) {
+                    return Err(ProofError::BadLonelySibling(level))
+                }
+                hash_pair(&current_hash, right_hash, hasher_builder)
+            },
+            _ => return Err(ProofError::WrongDirection(level))
+        };
+    }
+    if ct_eq(current_hash.as_ref(), root_hash.as_ref()) {
+        Ok(())
+    } else {
+        Err(ProofError::RootMismatch)
+    }
+}
+
+pub(crate) fn hash_pair<S>(left: &HashValue<S>, right: &HashValue<S>, hasher_builder: &S) -> HashValue<S>
+where
+    S: BuildMerkleHasher
+{
+    hasher_builder.hash_branch(left.as_ref(), right.as_ref())
+}
+
+// Hash of the leaf a value ends up in
+pub fn leaf_hash<V, S>(value: &V, hasher_builder: &S) -> HashValue<S>
+where
+    V: Hash,
+    S: BuildMerkleHasher
+{
+    let mut hasher = hasher_builder.build_hasher();
+    value.hash(&mut hasher);
+    hasher.finish_full()
+}
+
+
+// Value type that can't be constructed, so a tree using it
+//  can only be filled through insert_hash()
+#[derive(Clone, Hash)]
+pub enum NoValue {}
+
+pub type HashTree<S> = MerkleTree<NoValue, S>;
+
+// What inserting a leaf whose hash is already in the tree does.
+// Allow keeps both, tell them apart with index_proof().
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DuplicatePolicy {
+    Allow,
+    Reject,
+    Deduplicate
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InsertError {
+    // The leaf is already at this position,
+    //  or would have been earlier in the same batch
+    Duplicate(usize)
+}
+
 
 pub struct MerkleTree<V, S>
 where 
@@ -62,7 +217,10 @@ where
 {
     hasher_builder: S,
     nodes: VecDeque<Child<V, S>>,
-    root: Option<Child<V,S>>
+    root: Option<Child<V,S>>,
+    // Positions of every leaf hash, see enable_leaf_index()
+    leaf_index: Option<HashMap<HashValue<S>, Vec<usize>>>,
+    duplicate_policy: DuplicatePolicy
 }
 
 impl<V, S> MerkleTree<V, S>
@@ -74,27 +232,172 @@ where
         MerkleTree {
             hasher_builder: hasher_builder,
             nodes: VecDeque::new(),
-            root: None
+            root: None,
+            leaf_index: None,
+            duplicate_policy: DuplicatePolicy::Allow
+        }
+    }
+
+    // Applies to leaves inserted from now on, duplicates already
+    //  in the tree stay. Anything but Allow enables the leaf index.
+    pub fn set_duplicate_policy(&mut self, policy: DuplicatePolicy) {
+        if policy != DuplicatePolicy::Allow {
+            self.enable_leaf_index();
+        }
+        self.duplicate_policy = policy;
+    }
+
+    pub fn duplicate_policy(&self) -> DuplicatePolicy {
+        self.duplicate_policy
+    }
+
+    // Keeps a map from leaf hashes to their positions from now on,
+    //  so leaf_positions() and hash_proof() don't search the tree
+    pub fn enable_leaf_index(&mut self) {
+        if self.leaf_index.is_some() {
+            return
+        }
+        let mut index = HashMap::new();
+        for position in 0..self.leaf_count().unwrap_or(0) {
+            if let Some(leaf) = self.node_at(0, position) {
+                index.entry(leaf.hash_value()).or_insert_with(Vec::new).push(position);
+            }
+        }
+        self.leaf_index = Some(index);
+    }
+
+    // Empty tree with the same hasher, duplicate policy and leaf index
+    pub(crate) fn empty_like(&self) -> MerkleTree<V, S> {
+        let mut tree = MerkleTree::with_hasher(self.hasher_builder.clone());
+        tree.duplicate_policy = self.duplicate_policy;
+        if self.leaf_index.is_some() {
+            tree.leaf_index = Some(HashMap::new());
+        }
+        tree
+    }
+
+    // Every position holding a leaf with this hash, in order.
+    // O(1) with the leaf index enabled, a walk over the tree otherwise.
+    pub fn leaf_positions(&self, hash: &HashValue<S>) -> Vec<usize> {
+        match self.leaf_index {
+            Some(ref index) => index.get(hash).cloned().unwrap_or(vec![]),
+            None => {
+                let mut positions = vec![];
+                if let Some(ref root) = self.root {
+                    collect_positions(root, hash.as_ref(), 0, &mut positions);
+                }
+                positions
+            }
         }
     }
 
+    // The insert functions panic if the duplicate policy is Reject and
+    //  a leaf is already in the tree, the try_insert ones return an error.
     pub fn insert(&mut self, value: V) {
-        self.nodes.push_back(Box::new(Node::new_leaf(value, self.hasher_builder.clone())));
-        self.rebuild_tree();
+        let leaf = Box::new(Node::new_leaf(value, self.hasher_builder.clone()));
+        self.insert_leaves(Some(leaf))
+    }
+
+    pub fn try_insert(&mut self, value: V) -> Result<(), InsertError> {
+        let leaf = Box::new(Node::new_leaf(value, self.hasher_builder.clone()));
+        self.try_insert_leaves(Some(leaf))
     }
 
     pub fn insert_items<T>(&mut self, items: T)
     where
         T: IntoIterator<Item=V>
     {
-        for item in items {
-            let leaf = Box::new(Node::new_leaf(item, self.hasher_builder.clone()));
-            self.nodes.push_back(leaf)
+        let hasher_builder = self.hasher_builder.clone();
+        self.insert_leaves(items.into_iter()
+            .map(|item| Box::new(Node::new_leaf(item, hasher_builder.clone()))))
+    }
+
+    // Inserts all items or, if one is rejected, none of them
+    pub fn try_insert_items<T>(&mut self, items: T) -> Result<(), InsertError>
+    where
+        T: IntoIterator<Item=V>
+    {
+        let hasher_builder = self.hasher_builder.clone();
+        self.try_insert_leaves(items.into_iter()
+            .map(|item| Box::new(Node::new_leaf(item, hasher_builder.clone()))))
+    }
+
+    pub fn insert_hash(&mut self, hash: HashValue<S>) {
+        let leaf = Box::new(Node::new_hash_leaf(hash, self.hasher_builder.clone()));
+        self.insert_leaves(Some(leaf))
+    }
+
+    pub fn try_insert_hash(&mut self, hash: HashValue<S>) -> Result<(), InsertError> {
+        let leaf = Box::new(Node::new_hash_leaf(hash, self.hasher_builder.clone()));
+        self.try_insert_leaves(Some(leaf))
+    }
+
+    pub fn insert_hashes<T>(&mut self, hashes: T)
+    where
+        T: IntoIterator<Item=HashValue<S>>
+    {
+        let hasher_builder = self.hasher_builder.clone();
+        self.insert_leaves(hashes.into_iter()
+            .map(|hash| Box::new(Node::new_hash_leaf(hash, hasher_builder.clone()))))
+    }
+
+    pub fn try_insert_hashes<T>(&mut self, hashes: T) -> Result<(), InsertError>
+    where
+        T: IntoIterator<Item=HashValue<S>>
+    {
+        let hasher_builder = self.hasher_builder.clone();
+        self.try_insert_leaves(hashes.into_iter()
+            .map(|hash| Box::new(Node::new_hash_leaf(hash, hasher_builder.clone()))))
+    }
+
+    pub(crate) fn insert_leaves<T>(&mut self, leaves: T)
+    where
+        T: IntoIterator<Item=Child<V, S>>
+    {
+        if let Err(InsertError::Duplicate(position)) = self.try_insert_leaves(leaves) {
+            panic!("duplicate of leaf {} rejected, use try_insert to handle it", position)
         }
+    }
+
+    // Appends ready-made leaf nodes, rebuilding the tree once
+    pub(crate) fn try_insert_leaves<T>(&mut self, leaves: T) -> Result<(), InsertError>
+    where
+        T: IntoIterator<Item=Child<V, S>>
+    {
+        let leaves = self.admit(leaves.into_iter().collect())?;
+        self.nodes.extend(leaves);
         self.rebuild_tree();
+        Ok(())
+    }
+
+    // Applies the duplicate policy to leaves about to be inserted
+    fn admit(&self, leaves: Vec<Child<V, S>>) -> Result<Vec<Child<V, S>>, InsertError> {
+        let index = match (self.duplicate_policy, &self.leaf_index) {
+            (DuplicatePolicy::Allow, _) | (_, &None) => return Ok(leaves),
+            (_, &Some(ref index)) => index
+        };
+        let first = self.leaf_count().unwrap_or(0);
+        let mut batch = HashMap::new();
+        let mut admitted = Vec::with_capacity(leaves.len());
+        for leaf in leaves {
+            let hash = leaf.hash_value();
+            let existing = index.get(&hash).map(|positions| positions[0])
+                .or_else(|| batch.get(&hash).cloned());
+            match existing {
+                Some(position) if self.duplicate_policy == DuplicatePolicy::Reject => {
+                    return Err(InsertError::Duplicate(position))
+                },
+                Some(_) => (),
+                None => {
+                    batch.insert(hash, first + admitted.len());
+                    admitted.push(leaf);
+                }
+            }
+        }
+        Ok(admitted)
     }
 
-    pub fn root_hash(&self) -> Option<Box<[u8]>> {
+    pub fn root_hash(&self) -> Option<HashValue<S>> {
         if let Some(ref root) = self.root {
             Some(root.hash_value())
         } else {
@@ -107,25 +410,39 @@ where
         &self.root
     }
 
-    pub fn value_proof(&self, value: &V) -> Vec<Proof> {
-        if let Some(ref root) = self.root {
-            let node = Box::new(Node::new_leaf(value.clone(), self.hasher_builder.clone()));
-            self.data_proof(&node, &mut vec![root])
-        } else {
-            vec![]
-        }
+    pub fn value_proof(&self, value: &V) -> Vec<Proof<HashValue<S>>> {
+        let leaf = Node::new_leaf(value.clone(), self.hasher_builder.clone());
+        self.hash_proof(&leaf.hash_value())
     }
 
-    pub fn tree_proof(&self, tree: MerkleTree<V, S>) -> Vec<Proof> {
-        if let &Some(ref target) = tree.root() {
-            if let Some(ref root) = self.root {
-                self.data_proof(target,&mut vec![root])
-            } else {
-                vec![]
-            }
-        } else {
-            vec![]
-        }
+    pub fn hash_proof(&self, hash: &HashValue<S>) -> Vec<Proof<HashValue<S>>> {
+        let position = match self.leaf_index {
+            Some(ref index) => index.get(hash).map(|positions| positions[0]),
+            None => self.find(0, hash.as_ref())
+        };
+        position
+            .and_then(|index| self.subtree_proof(0, index))
+            .unwrap_or(vec![])
+    }
+
+    // Position of a tree that is one of our subtrees, as the index of
+    //  its root at its own height, and the proof for that position.
+    // None if no node there has its root hash.
+    pub fn tree_proof(&self, tree: &MerkleTree<V, S>) -> Option<(usize, Vec<Proof<HashValue<S>>>)> {
+        let target = tree.root().as_ref()?;
+        let level = target.height();
+        let index = self.find(level, target.hash_ref())?;
+        Some((index, self.subtree_proof(level, index)?))
+    }
+
+    // Membership proof for the leaf at the given position.
+    // Walks a single path from the root, so it's O(log n).
+    pub fn index_proof(&self, index: usize) -> Option<Vec<Proof<HashValue<S>>>> {
+        self.subtree_proof(0, index)
+    }
+
+    pub fn index_proof_ref(&self, index: usize) -> Option<Vec<Proof<&[u8]>>> {
+        self.subtree_proof_ref(0, index)
     }
 
     pub fn height(&self) -> usize {
@@ -148,107 +465,294 @@ where
         self.hasher_builder.clone()
     }
 
-    fn data_proof<'a>(
-        &self, target: &Child<V, S>, 
-        parent_stack: &mut Vec<&'a Child<V, S>>) -> Vec<Proof> 
-    {
-        match *parent_stack.as_slice() {
-            [] => vec![],
-
-            [.., parent] if target.height() + 1 == parent.height() => {
-                let parent = parent_stack.pop().unwrap();
-                match (parent.left(), parent.right()) {
-                    (&Some(ref left), &Some(ref right)) if left.hash_value() == target.hash_value() => {
-                        let mut result = vec![Proof::Right(right.hash_value())];
-                        result.append(&mut self.data_proof(parent, parent_stack));
-                        result
-                    },
-                    (&Some(ref left), &Some(ref right)) if right.hash_value() == target.hash_value() => {
-                        let mut result = vec![Proof::Left(left.hash_value())];
-                        result.append(&mut self.data_proof(parent, parent_stack));
-                        result
-                    },
-                    (&Some(ref left), _) if left.hash_value() == target.hash_value() => {
-                        let mut result = vec![Proof::Right(left.hash_value())];
-                        result.append(&mut self.data_proof(parent, parent_stack));
-                        result
-                    },
-                    (_, &Some(ref right)) if right.hash_value() == target.hash_value() => {
-                        let mut result = vec![Proof::Left(right.hash_value())];
-                        result.append(&mut self.data_proof(parent, parent_stack));
-                        result
-                    },
-                    _ => vec![]
-                }
-            },
+    // Node of the given height (0 for leaves) and index within that level,
+    //  covering leaves [index * 2^level, (index + 1) * 2^level)
+    pub fn node_at(&self, level: usize, index: usize) -> Option<&Child<V, S>> {
+        let mut node = self.root.as_ref()?;
+        let height = node.height();
+        if level > height || index >> (height - level) != 0 {
+            return None
+        }
+        for current in (level..height).rev() {
+            let child = if (index >> (current - level)) & 1 == 0 {
+                node.left()
+            } else {
+                node.right()
+            };
+            node = child.as_ref()?;
+        }
+        Some(node)
+    }
 
-            [.., parent] => {
-                match (parent.left(), parent.right()) {
-                    (&Some(ref left), &Some(ref right)) => {
-                        parent_stack.push(left);
-                        let mut result = self.data_proof(target, parent_stack);
-                        parent_stack.push(right);
-                        result.append(&mut self.data_proof(target, parent_stack));
-                        result
-                    },
-                    (&Some(ref left), _) if left.hash_value() == target.hash_value() => {
-                        parent_stack.push(left);
-                        self.data_proof(target, parent_stack)
-                    },
-                    (_, &Some(ref right)) if right.hash_value() == target.hash_value() => {
-                        parent_stack.push(right);
-                        self.data_proof(target, parent_stack)
-                    },
-                    _ => vec![]
-                }
+    pub fn leaf(&self, index: usize) -> Option<&V> {
+        self.node_at(0, index)?.value()
+    }
+
+    // Proof from the node at (level, index) up to the root,
+    //  siblings are listed bottom-up. See verify_subtree().
+    pub fn subtree_proof(&self, level: usize, index: usize) -> Option<Vec<Proof<HashValue<S>>>> {
+        let siblings = self.subtree_siblings(level, index)?;
+        Some(siblings.into_iter().map(|piece| match piece {
+            Proof::Left(node) => Proof::Left(node.hash_value()),
+            Proof::Right(node) => Proof::Right(node.hash_value())
+        }).collect())
+    }
+
+    // Same as subtree_proof(), borrowing the hashes from the tree
+    pub fn subtree_proof_ref(&self, level: usize, index: usize) -> Option<Vec<Proof<&[u8]>>> {
+        let siblings = self.subtree_siblings(level, index)?;
+        Some(siblings.into_iter().map(|piece| match piece {
+            Proof::Left(node) => Proof::Left(node.hash_ref()),
+            Proof::Right(node) => Proof::Right(node.hash_ref())
+        }).collect())
+    }
+
+    fn subtree_siblings(&self, level: usize, index: usize) -> Option<Vec<Proof<&Child<V, S>>>> {
+        let mut node = self.root.as_ref()?;
+        let height = node.height();
+        if level > height || index >> (height - level) != 0 {
+            return None
+        }
+        let mut proof = Vec::with_capacity(height - level);
+        for current in (level..height).rev() {
+            let left = node.left().as_ref()?;
+            if (index >> (current - level)) & 1 == 0 {
+                // Siblingless nodes are paired with themselves
+                let sibling = match node.right() {
+                    &Some(ref right) => right,
+                    _ => left
+                };
+                proof.push(Proof::Right(sibling));
+                node = left;
+            } else {
+                proof.push(Proof::Left(left));
+                node = node.right().as_ref()?;
             }
         }
+        proof.reverse();
+        Some(proof)
     }
 
-    fn rebuild_tree(&mut self) {
-        let nodes_len = self.nodes_leaf_count();
+    // Index of the leftmost node at the given level with the given hash
+    fn find(&self, level: usize, hash: &[u8]) -> Option<usize> {
         match self.root {
-            _ if self.nodes.is_empty() => self.root = None,
-            Some(ref node) if node.leaf_count() >= self.nodes_leaf_count() => (),
-            _ => {
-                let root = self.build_tree(f64::log2(nodes_len as f64).ceil() as usize);
-                self.nodes = VecDeque::new();
-                if let Some(ref unwrapped_root) = root {
-                    self.recycle(unwrapped_root)
-                }
-                self.root = root;
+            Some(ref root) if root.height() >= level => find_node(root, level, hash, 0),
+            _ => None
+        }
+    }
+
+    fn rebuild_tree(&mut self) {
+        if let Some((mut nodes, height)) = self.take_nodes() {
+            self.root = build_tree(&mut nodes, height, &self.hasher_builder);
+        }
+    }
+
+    // Takes the root apart into final nodes followed by pending leaves,
+    //  along with the height of the tree they add up to.
+    // None if there are no pending leaves.
+    fn take_nodes(&mut self) -> Option<(VecDeque<Child<V, S>>, usize)> {
+        if self.nodes.is_empty() {
+            return None
+        }
+        if let Some(ref mut index) = self.leaf_index {
+            let first = self.root.as_ref().map_or(0, |root| root.leaf_count());
+            for (offset, leaf) in self.nodes.iter().enumerate() {
+                index.entry(leaf.hash_value()).or_insert_with(Vec::new).push(first + offset);
             }
+        }
+        let mut nodes = VecDeque::new();
+        if let Some(root) = self.root.take() {
+            recycle(root, &mut nodes);
+        }
+        nodes.append(&mut self.nodes);
+        let leaf_count = nodes.iter()
+            .fold(0, |acc, child| acc + child.leaf_count());
+        Some((nodes, height_for(leaf_count)))
+    }
+}
+
+impl<V, S> MerkleTree<V, S>
+where
+    V: Hash + Clone + Send,
+    S: BuildMerkleHasher + Send + Sync
+{
+    // Same as insert_items(), but spreads hashing over `threads` threads.
+    // Zero means one thread per available core.
+    pub fn insert_items_parallel<T>(&mut self, items: T, threads: usize)
+    where
+        T: IntoIterator<Item=V>
+    {
+        if let Err(InsertError::Duplicate(position)) = self.try_insert_items_parallel(items, threads) {
+            panic!("duplicate of leaf {} rejected, use try_insert to handle it", position)
+        }
+    }
+
+    pub fn try_insert_items_parallel<T>(&mut self, items: T, threads: usize) -> Result<(), InsertError>
+    where
+        T: IntoIterator<Item=V>
+    {
+        let threads = match threads {
+            0 => thread::available_parallelism().map(|count| count.get()).unwrap_or(1),
+            _ => threads
         };
+        let leaves = hash_leaves(items.into_iter().collect(), &self.hasher_builder, threads);
+        let leaves = self.admit(leaves)?;
+        self.nodes.extend(leaves);
+        if let Some((nodes, height)) = self.take_nodes() {
+            self.root = build_parallel(nodes, height, &self.hasher_builder, threads);
+        }
+        Ok(())
     }
+}
 
-    fn build_tree(&mut self, height: usize) -> Option<Child<V, S>> {
-        let front_height = self.nodes.front()?.height();
-        if front_height == height {
-            Some(self.nodes.pop_front().unwrap())
-        } else {
-            let left = self.build_tree(height - 1);
-            let right = self.build_tree(height - 1);
-            Some(Box::new(Node::new_branch(left, right, self.hasher_builder.clone())))
+// Subtrees with fewer leaves than this are not worth a thread
+const PARALLEL_THRESHOLD: usize = 1024;
+
+// Height of a tree holding the given number of leaves
+pub(crate) fn height_for(leaf_count: usize) -> usize {
+    if leaf_count <= 1 {
+        return 0
+    }
+    (usize::BITS - (leaf_count - 1).leading_zeros()) as usize
+}
+
+fn find_node<V, S>(node: &Child<V, S>, level: usize, hash: &[u8], index: usize) -> Option<usize>
+where
+    V: Hash + Clone,
+    S: BuildMerkleHasher
+{
+    if node.height() == level {
+        return if ct_eq(node.hash_ref(), hash) { Some(index) } else { None }
+    }
+    let left = node.left().as_ref()
+        .and_then(|left| find_node(left, level, hash, 2 * index));
+    left.or_else(|| {
+        node.right().as_ref()
+            .and_then(|right| find_node(right, level, hash, 2 * index + 1))
+    })
+}
+
+fn collect_positions<V, S>(node: &Child<V, S>, hash: &[u8], first_leaf: usize, positions: &mut Vec<usize>)
+where
+    V: Hash + Clone,
+    S: BuildMerkleHasher
+{
+    if node.height() == 0 {
+        if ct_eq(node.hash_ref(), hash) {
+            positions.push(first_leaf);
         }
+        return
     }
+    if let &Some(ref left) = node.left() {
+        collect_positions(left, hash, first_leaf, positions);
+    }
+    if let &Some(ref right) = node.right() {
+        collect_positions(right, hash, first_leaf + (1 << (node.height() - 1)), positions);
+    }
+}
 
-    fn recycle(&mut self, root: &Child<V, S>) {
-        if root.is_final() {
-            self.nodes.push_back(root.clone())
-        } else {
-            if let &Some(ref left) = root.left() {
-                self.recycle(left);
-            }
-            if let &Some(ref right) = root.right() {
-                self.recycle(right);
-            }
+// Moves final nodes out of a tree in order, taking apart the rest.
+// Only the right edge of a tree is not final, so that's O(log n) nodes.
+fn recycle<V, S>(node: Child<V, S>, nodes: &mut VecDeque<Child<V, S>>)
+where
+    V: Hash + Clone,
+    S: BuildMerkleHasher
+{
+    if node.is_final() {
+        nodes.push_back(node)
+    } else {
+        let (left, right) = (*node).into_children();
+        if let Some(left) = left {
+            recycle(left, nodes);
         }
+        if let Some(right) = right {
+            recycle(right, nodes);
+        }
+    }
+}
+
+fn build_tree<V, S>(nodes: &mut VecDeque<Child<V, S>>, height: usize, hasher_builder: &S) -> Option<Child<V, S>>
+where
+    V: Hash + Clone,
+    S: BuildMerkleHasher
+{
+    let front_height = nodes.front()?.height();
+    if front_height == height {
+        Some(nodes.pop_front().unwrap())
+    } else {
+        let left = build_tree(nodes, height - 1, hasher_builder);
+        let right = build_tree(nodes, height - 1, hasher_builder);
+        Some(Box::new(Node::new_branch(left, right, hasher_builder.clone())))
+    }
+}
+
+// Builds left and right subtrees on separate threads.
+// Nodes are split where the left subtree runs out of leaves,
+//  which is exactly what build_tree() does sequentially.
+fn build_parallel<V, S>(
+    mut nodes: VecDeque<Child<V, S>>,
+    height: usize,
+    hasher_builder: &S,
+    threads: usize) -> Option<Child<V, S>>
+where
+    V: Hash + Clone + Send,
+    S: BuildMerkleHasher + Send + Sync
+{
+    let leaf_count = nodes.iter()
+        .fold(0, |acc, child| acc + child.leaf_count());
+    if threads <= 1 || leaf_count < PARALLEL_THRESHOLD || nodes.front()?.height() == height {
+        return build_tree(&mut nodes, height, hasher_builder)
+    }
+
+    let half = 1 << (height - 1);
+    let mut covered = 0;
+    let mut split = 0;
+    while split < nodes.len() && covered < half {
+        covered += nodes[split].leaf_count();
+        split += 1;
+    }
+    let right_nodes = nodes.split_off(split);
+    let (left, right) = thread::scope(|scope| {
+        let right = scope.spawn(move || build_parallel(right_nodes, height - 1, hasher_builder, threads / 2));
+        let left = build_parallel(nodes, height - 1, hasher_builder, threads - threads / 2);
+        (left, right.join().unwrap())
+    });
+    Some(Box::new(Node::new_branch(left, right, hasher_builder.clone())))
+}
+
+// Hashes leaves in contiguous chunks, one chunk per thread
+fn hash_leaves<V, S>(mut items: Vec<V>, hasher_builder: &S, threads: usize) -> Vec<Child<V, S>>
+where
+    V: Hash + Clone + Send,
+    S: BuildMerkleHasher + Send + Sync
+{
+    let chunk_len = (items.len() + threads - 1) / threads;
+    if threads <= 1 || items.len() < PARALLEL_THRESHOLD {
+        return items.into_iter()
+            .map(|item| Box::new(Node::new_leaf(item, hasher_builder.clone())))
+            .collect()
     }
 
-    fn nodes_leaf_count(&self) -> usize {
-        self.nodes.iter()
-            .fold(0, |acc, child| acc + child.leaf_count())
+    let mut chunks = vec![];
+    while items.len() > chunk_len {
+        let start = (items.len() - 1) / chunk_len * chunk_len;
+        chunks.push(items.split_off(start));
     }
+    chunks.push(items);
+    chunks.reverse();
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = chunks.into_iter()
+            .map(|chunk| scope.spawn(move || {
+                chunk.into_iter()
+                    .map(|item| Box::new(Node::new_leaf(item, hasher_builder.clone())))
+                    .collect::<Vec<_>>()
+            }))
+            .collect();
+        handles.into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    })
 }
 
 impl<V, S> fmt::Display for MerkleTree<V, S>
@@ -262,4 +766,15 @@ where
             _ => write!(f, "")
         }
     }
-}
\ No newline at end of file
+}
+
+// Draws the tree, see pretty.rs
+impl<V, S> fmt::Debug for MerkleTree<V, S>
+where
+    V: Hash + Clone,
+    S: BuildMerkleHasher
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", pretty(self, PrettyOptions::default()))
+    }
+}